@@ -0,0 +1,254 @@
+use crate::base64;
+use crate::crypto::{constant_time_eq, sha256};
+use crate::types::{
+    Authentication, Credentials, HeaderMethods, HttpStatusCode, Middleware, Request, Response,
+};
+
+// hashing first means the comparison doesn't leak the length of the secret either
+fn secure_eq(a: &str, b: &str) -> bool {
+    constant_time_eq(&sha256(a.as_bytes()), &sha256(b.as_bytes()))
+}
+
+impl Credentials {
+    pub(crate) fn parse(header: &str) -> Option<Credentials> {
+        let (scheme, value) = header.trim().split_once(' ')?;
+        let value = value.trim();
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = String::from_utf8(base64::decode(value, false)?).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+
+            Some(Credentials::Basic {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            })
+        } else if scheme.eq_ignore_ascii_case("bearer") && !value.is_empty() {
+            Some(Credentials::Bearer(value.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Credentials::Basic { username, .. } => Some(username),
+            Credentials::Bearer(_) => None,
+        }
+    }
+
+    // both checks take the same time however much of the secrets match
+    pub fn matches_basic(&self, expected_username: &str, expected_password: &str) -> bool {
+        match self {
+            Credentials::Basic { username, password } => {
+                // `&` rather than `&&` so a wrong username takes as long as a wrong password
+                secure_eq(username, expected_username) & secure_eq(password, expected_password)
+            }
+            Credentials::Bearer(_) => false,
+        }
+    }
+
+    pub fn matches_bearer(&self, expected_token: &str) -> bool {
+        match self {
+            Credentials::Bearer(token) => secure_eq(token, expected_token),
+            Credentials::Basic { .. } => false,
+        }
+    }
+}
+
+impl Request {
+    // `Basic` or `Bearer` credentials from the `Authorization` header
+    pub fn credentials(&self) -> Option<Credentials> {
+        Credentials::parse(self.headers.get_header("Authorization")?)
+    }
+}
+
+impl Authentication {
+    pub fn basic<R, F>(realm: R, verify: F) -> Authentication
+    where
+        R: Into<String>,
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        Authentication {
+            prefix: "/".to_owned(),
+            scheme: "Basic",
+            realm: realm.into(),
+            verify: Box::new(move |credentials| match credentials {
+                Credentials::Basic { username, password } => verify(username, password),
+                Credentials::Bearer(_) => false,
+            }),
+        }
+    }
+
+    pub fn bearer<R, F>(realm: R, verify: F) -> Authentication
+    where
+        R: Into<String>,
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        Authentication {
+            prefix: "/".to_owned(),
+            scheme: "Bearer",
+            realm: realm.into(),
+            verify: Box::new(move |credentials| match credentials {
+                Credentials::Bearer(token) => verify(token),
+                Credentials::Basic { .. } => false,
+            }),
+        }
+    }
+
+    // only requests under this path need credentials, e.g. `/admin`
+    pub fn under<S>(mut self, prefix: S) -> Authentication
+    where
+        S: Into<String>,
+    {
+        let prefix = prefix.into();
+        self.prefix = format!("/{}/", prefix.trim_matches('/')).replace("//", "/");
+        self
+    }
+
+    fn challenge(&self, credentials: Option<&Credentials>) -> String {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut challenge = format!("{} realm=\"{}\"", self.scheme, realm);
+
+        match self.scheme {
+            "Basic" => challenge.push_str(", charset=\"UTF-8\""),
+            // RFC 6750 tells a client apart that sent a bad token from one that sent none
+            _ if credentials.is_some() => challenge.push_str(", error=\"invalid_token\""),
+            _ => {}
+        }
+
+        challenge
+    }
+}
+
+impl Middleware for Authentication {
+    fn before(&self, req: &mut Request, res: &mut Response) -> Option<HttpStatusCode> {
+        if !req.path.starts_with(&self.prefix) {
+            return None;
+        }
+
+        let credentials = req.credentials();
+        if credentials.as_ref().is_some_and(|c| (self.verify)(c)) {
+            return None;
+        }
+
+        res.text("Unauthorized");
+        res.headers.set_header(
+            "WWW-Authenticate".to_owned(),
+            self.challenge(credentials.as_ref()),
+        );
+
+        Some(HttpStatusCode::Code401)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+
+    fn request(path: &str, authorization: Option<&str>) -> Request {
+        let mut req = Request {
+            path: path.to_owned(),
+            ..Default::default()
+        };
+        if let Some(authorization) = authorization {
+            req.headers.set_header("Authorization", authorization);
+        }
+
+        req
+    }
+
+    #[test]
+    fn it_parses_credentials() {
+        // "aladdin:open sesame"
+        assert_eq!(
+            Credentials::parse("Basic YWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+            Some(Credentials::Basic {
+                username: "aladdin".to_owned(),
+                password: "open sesame".to_owned(),
+            })
+        );
+        // passwords may contain colons, usernames can't
+        assert_eq!(
+            Credentials::parse("basic dTpwOnc="),
+            Some(Credentials::Basic {
+                username: "u".to_owned(),
+                password: "p:w".to_owned(),
+            })
+        );
+        assert_eq!(
+            Credentials::parse("Bearer abc.def-ghi"),
+            Some(Credentials::Bearer("abc.def-ghi".to_owned()))
+        );
+
+        assert_eq!(Credentials::parse("Basic !!!"), None);
+        assert_eq!(Credentials::parse("Basic bm9jb2xvbg=="), None);
+        assert_eq!(Credentials::parse("Bearer "), None);
+        assert_eq!(Credentials::parse("Digest username=x"), None);
+        assert_eq!(request("/", None).credentials(), None);
+    }
+
+    #[test]
+    fn it_compares_credentials() {
+        let basic = Credentials::parse("Basic YWxhZGRpbjpvcGVuIHNlc2FtZQ==").unwrap();
+        assert!(basic.matches_basic("aladdin", "open sesame"));
+        assert!(!basic.matches_basic("aladdin", "open sesame!"));
+        assert!(!basic.matches_basic("genie", "open sesame"));
+        assert!(!basic.matches_bearer("open sesame"));
+        assert_eq!(basic.username(), Some("aladdin"));
+
+        let bearer = Credentials::Bearer("token".to_owned());
+        assert!(bearer.matches_bearer("token"));
+        assert!(!bearer.matches_bearer("toke"));
+        assert!(!bearer.matches_basic("token", ""));
+    }
+
+    #[test]
+    fn it_challenges_requests() {
+        let auth = Authentication::basic("admin \"area\"", |username, password| {
+            username == "aladdin" && password == "open sesame"
+        })
+        .under("/admin");
+
+        let mut res = test_response();
+        let mut req = request("/admin/users/", None);
+        assert_eq!(
+            auth.before(&mut req, &mut res),
+            Some(HttpStatusCode::Code401)
+        );
+        assert_eq!(
+            res.headers
+                .get_header("WWW-Authenticate")
+                .map(String::as_str),
+            Some("Basic realm=\"admin \\\"area\\\"\", charset=\"UTF-8\"")
+        );
+
+        let mut req = request("/admin/", Some("Basic YWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+        assert_eq!(auth.before(&mut req, &mut test_response()), None);
+        let mut req = request("/public/", None);
+        assert_eq!(auth.before(&mut req, &mut test_response()), None);
+        let mut req = request("/admin/", Some("Bearer open-sesame"));
+        assert_eq!(
+            auth.before(&mut req, &mut test_response()),
+            Some(HttpStatusCode::Code401)
+        );
+
+        let auth = Authentication::bearer("api", |token| token == "secret");
+        let mut req = request("/", Some("Bearer secret"));
+        assert_eq!(auth.before(&mut req, &mut test_response()), None);
+
+        let mut res = test_response();
+        let mut req = request("/", Some("Bearer wrong"));
+        assert_eq!(
+            auth.before(&mut req, &mut res),
+            Some(HttpStatusCode::Code401)
+        );
+        assert_eq!(
+            res.headers
+                .get_header("WWW-Authenticate")
+                .map(String::as_str),
+            Some("Bearer realm=\"api\", error=\"invalid_token\"")
+        );
+    }
+}
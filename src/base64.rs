@@ -0,0 +1,75 @@
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// the standard alphabet is padded, the url safe one isn't since `=` has to be escaped in urls and cookies
+pub(crate) fn encode(data: &[u8], url_safe: bool) -> String {
+    let alphabet = if url_safe { URL_SAFE } else { STANDARD };
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let group = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+
+        for i in 0..=chunk.len() {
+            encoded.push(alphabet[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+        if !url_safe {
+            for _ in chunk.len()..3 {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+pub(crate) fn decode(input: &str, url_safe: bool) -> Option<Vec<u8>> {
+    let alphabet = if url_safe { URL_SAFE } else { STANDARD };
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut group = 0u32;
+        for (i, chr) in chunk.iter().enumerate() {
+            let value = alphabet.iter().position(|a| a == chr)? as u32;
+            group |= value << (18 - 6 * i);
+        }
+
+        for i in 0..chunk.len() - 1 {
+            decoded.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips() {
+        assert_eq!(encode(b"", false), "");
+        assert_eq!(encode(b"f", false), "Zg==");
+        assert_eq!(encode(b"fo", false), "Zm8=");
+        assert_eq!(encode(b"foo", false), "Zm9v");
+        assert_eq!(encode(b"foob", true), "Zm9vYg");
+        assert_eq!(encode(&[0xfb, 0xff], true), "-_8");
+        assert_eq!(encode(&[0xfb, 0xff], false), "+/8=");
+
+        assert_eq!(decode("Zm9vYmFy", false), Some(b"foobar".to_vec()));
+        assert_eq!(decode("Zm9vYg==", false), Some(b"foob".to_vec()));
+        assert_eq!(decode("Zm9vYg", true), Some(b"foob".to_vec()));
+        assert_eq!(decode("-_8", true), Some(vec![0xfb, 0xff]));
+        assert_eq!(decode("-_8", false), None);
+        assert_eq!(decode("Zm9vY", false), None);
+        assert_eq!(decode("Zm9v!A==", false), None);
+    }
+}
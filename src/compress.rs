@@ -0,0 +1,221 @@
+use crate::deflate::{gzip, zlib};
+use crate::negotiate::quality_values;
+use crate::types::{
+    Compression, ContentCoding, HeaderMethods, HttpStatusCode, Method, Middleware, Request,
+    Response,
+};
+
+// formats that are already compressed only get bigger when compressed again
+const COMPRESSED_TYPES: [&str; 12] = [
+    "application/octet-stream",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/zstd",
+    "application/pdf",
+    "font/woff",
+    "font/woff2",
+];
+
+fn is_compressible(content_type: Option<&String>) -> bool {
+    let media_type = match content_type {
+        Some(content_type) => content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase(),
+        None => return true,
+    };
+
+    match media_type.split_once('/') {
+        Some(("image", subtype)) => subtype == "svg+xml",
+        Some(("video" | "audio", _)) => false,
+        _ => !COMPRESSED_TYPES.contains(&media_type.as_str()),
+    }
+}
+
+// picks the best coding we support from `Accept-Encoding`, preferring gzip on ties;
+// a missing header means the client didn't ask for compression
+pub(crate) fn choose_coding(header: Option<&String>) -> Option<ContentCoding> {
+    let accepted = quality_values(header?);
+    let quality = |coding: &str| {
+        accepted
+            .iter()
+            .find(|(value, _)| value == coding)
+            .or_else(|| accepted.iter().find(|(value, _)| value == "*"))
+            .map(|(_, quality)| *quality)
+    };
+
+    let gzip = quality("gzip")
+        .or_else(|| {
+            accepted
+                .iter()
+                .find(|(value, _)| value == "x-gzip")
+                .map(|(_, q)| *q)
+        })
+        .unwrap_or(0.0);
+    let deflate = quality("deflate").unwrap_or(0.0);
+    let identity = quality("identity").unwrap_or(1.0);
+
+    let (coding, best) = if gzip >= deflate {
+        (ContentCoding::Gzip, gzip)
+    } else {
+        (ContentCoding::Deflate, deflate)
+    };
+
+    (best > 0.0 && best >= identity).then_some(coding)
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression { threshold: 1024 }
+    }
+
+    // bodies smaller than this are sent as they are
+    pub fn threshold(mut self, bytes: usize) -> Compression {
+        self.threshold = bytes;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, req: &Request, res: &mut Response) {
+        // partial content ranges refer to the uncompressed body
+        if req.method == Method::Head
+            || !res.status.is_success()
+            || res.status == HttpStatusCode::Code204
+            || res.status == HttpStatusCode::Code206
+            || res.headers.get_header("Content-Encoding").is_some()
+            || !is_compressible(res.headers.get_header("Content-Type"))
+        {
+            return;
+        }
+
+        // caches have to know the body depends on `Accept-Encoding` even when it wasn't compressed
+        res.add_vary("Accept-Encoding");
+        if res.body.len() < self.threshold {
+            return;
+        }
+
+        let coding = match choose_coding(req.headers.get_header("Accept-Encoding")) {
+            Some(coding) => coding,
+            None => return,
+        };
+        let (compressed, name) = match coding {
+            ContentCoding::Gzip => (gzip(&res.body), "gzip"),
+            ContentCoding::Deflate => (zlib(&res.body), "deflate"),
+        };
+        if compressed.len() >= res.body.len() {
+            return;
+        }
+
+        res.body = compressed;
+        res.headers.set_header("Content-Encoding", name);
+        res.headers.remove_header("Content-Length");
+
+        // a strong etag has to differ between encodings of the same resource
+        if let Some(etag) = res.headers.get_header("ETag") {
+            if let Some(opaque) = etag.strip_prefix('"').and_then(|e| e.strip_suffix('"')) {
+                let etag = format!("\"{}-{}\"", opaque, name);
+                res.headers.set_header("ETag".to_owned(), etag);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+
+    fn compress(accept_encoding: Option<&str>, content_type: &str, body: &str) -> Response {
+        let mut req = Request::default();
+        if let Some(accept_encoding) = accept_encoding {
+            req.headers.set_header("Accept-Encoding", accept_encoding);
+        }
+
+        let mut res = test_response();
+        res.body = body.into();
+        res.headers.set_header("Content-Type", content_type);
+        res.set_etag("v1");
+
+        Compression::new().after(&req, &mut res);
+        res
+    }
+
+    #[test]
+    fn it_chooses_codings() {
+        let choose = |header: &str| choose_coding(Some(&header.to_owned()));
+
+        assert_eq!(choose_coding(None), None);
+        assert_eq!(choose("gzip, deflate, br"), Some(ContentCoding::Gzip));
+        assert_eq!(choose("gzip;q=0.5, deflate"), Some(ContentCoding::Deflate));
+        assert_eq!(choose("*"), Some(ContentCoding::Gzip));
+        assert_eq!(choose("x-gzip"), Some(ContentCoding::Gzip));
+        assert_eq!(choose("br"), None);
+        assert_eq!(choose("gzip;q=0, *;q=0.3"), Some(ContentCoding::Deflate));
+        assert_eq!(choose("identity, gzip;q=0.5"), None);
+        assert_eq!(choose(""), None);
+    }
+
+    #[test]
+    fn it_compresses_responses() {
+        let html = "<p>rebar is a simple and minimalistic http server</p>\n".repeat(50);
+
+        let res = compress(Some("gzip, deflate"), "text/html", &html);
+        assert_eq!(&res.body[..2], &[0x1f, 0x8b]);
+        assert!(res.body.len() < html.len() / 4);
+        assert_eq!(
+            res.headers
+                .get_header("Content-Encoding")
+                .map(String::as_str),
+            Some("gzip")
+        );
+        assert_eq!(
+            res.headers.get_header("Vary").map(String::as_str),
+            Some("Accept-Encoding")
+        );
+        assert_eq!(
+            res.headers.get_header("ETag").map(String::as_str),
+            Some("\"v1-gzip\"")
+        );
+
+        let res = compress(Some("deflate"), "application/json", &html);
+        assert_eq!(res.body[0], 0x78);
+
+        let res = compress(None, "text/html", &html);
+        assert_eq!(res.body, html.as_bytes());
+        assert_eq!(res.headers.get_header("Content-Encoding"), None);
+        assert_eq!(
+            res.headers.get_header("Vary").map(String::as_str),
+            Some("Accept-Encoding")
+        );
+
+        let res = compress(Some("gzip"), "text/html", "<p>tiny</p>");
+        assert_eq!(res.body, b"<p>tiny</p>");
+
+        let res = compress(Some("gzip"), "image/png", &html);
+        assert_eq!(res.body, html.as_bytes());
+        assert_eq!(res.headers.get_header("Vary"), None);
+
+        let res = compress(Some("gzip"), "image/svg+xml", &html);
+        assert_eq!(
+            res.headers
+                .get_header("Content-Encoding")
+                .map(String::as_str),
+            Some("gzip")
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use crate::base64;
+use crate::crypto::sha256;
+use crate::date::{format_http_date, parse_http_date};
+use crate::types::{
+    ConditionalGet, HeaderMethods, HttpStatusCode, Method, Middleware, Request, Response,
+};
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// splits `W/"a", "b,c"` into entity tags, keeping the `W/` prefix and the quotes
+fn parse_etags(header: &str) -> Vec<&str> {
+    let mut etags = Vec::new();
+    let mut rest = header;
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        let start = rest.len();
+        let tag = rest.strip_prefix("W/").unwrap_or(rest);
+
+        let quoted = match tag.strip_prefix('"') {
+            Some(quoted) => quoted,
+            None => break,
+        };
+        let end = match quoted.find('"') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let len = start - quoted.len() + end + 1;
+        etags.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    etags
+}
+
+fn is_weak(etag: &str) -> bool {
+    etag.starts_with("W/")
+}
+
+fn opaque(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+// `Compression` tags compressed bodies `"x-gzip"` or `"x-deflate"`; they're the same resource
+// as `"x"` for weak comparison, but not for strong comparison since the bytes differ;
+// the closing quote is dropped so both compare as `"x`
+fn without_coding(opaque: &str) -> &str {
+    ["-gzip\"", "-deflate\"", "\""]
+        .iter()
+        .find_map(|suffix| opaque.strip_suffix(suffix).filter(|tag| tag.len() > 1))
+        .unwrap_or(opaque)
+}
+
+// `If-Match` and `If-Range` use strong comparison, `If-None-Match` uses weak comparison
+pub(crate) fn etag_matches(header: &str, etag: Option<&str>, weak: bool) -> bool {
+    if header.trim() == "*" {
+        return etag.is_some();
+    }
+
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return false,
+    };
+
+    parse_etags(header).into_iter().any(|candidate| {
+        if weak {
+            without_coding(opaque(candidate)) == without_coding(opaque(etag))
+        } else {
+            !is_weak(candidate) && !is_weak(etag) && opaque(candidate) == opaque(etag)
+        }
+    })
+}
+
+// http dates only have second precision
+pub(crate) fn truncate(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => time,
+    }
+}
+
+// a strong validator made from a file's size and modification time
+pub(crate) fn file_etag(len: u64, modified: Option<SystemTime>) -> String {
+    let nanos = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_nanos())
+        .unwrap_or(0);
+
+    format!("\"{:x}-{:x}\"", len, nanos)
+}
+
+impl Request {
+    // evaluates the precondition headers in the order RFC 9110 section 13.2.2 gives them,
+    // returning the status to answer with instead of running the request
+    pub fn check_preconditions(
+        &self,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Option<HttpStatusCode> {
+        let last_modified = last_modified.map(truncate);
+        let date = |name: &str| {
+            self.headers
+                .get_header(name)
+                .and_then(|header| parse_http_date(header))
+        };
+        let is_get = matches!(self.method, Method::Get | Method::Head);
+
+        if let Some(header) = self.headers.get_header("If-Match") {
+            if !etag_matches(header, etag, false) {
+                return Some(HttpStatusCode::Code412);
+            }
+        } else if let (Some(since), Some(modified)) = (date("If-Unmodified-Since"), last_modified) {
+            if modified > since {
+                return Some(HttpStatusCode::Code412);
+            }
+        }
+
+        if let Some(header) = self.headers.get_header("If-None-Match") {
+            if etag_matches(header, etag, true) {
+                return Some(match is_get {
+                    true => HttpStatusCode::Code304,
+                    false => HttpStatusCode::Code412,
+                });
+            }
+        } else if let (true, Some(since), Some(modified)) =
+            (is_get, date("If-Modified-Since"), last_modified)
+        {
+            if modified <= since {
+                return Some(HttpStatusCode::Code304);
+            }
+        }
+
+        None
+    }
+}
+
+impl Response {
+    // takes the tag with its quotes, e.g. `"v1"` or `W/"v1"`; bare values get quoted
+    pub fn set_etag(&mut self, etag: &str) -> &mut Self {
+        let etag = if etag.ends_with('"') {
+            etag.to_owned()
+        } else {
+            format!("\"{}\"", etag)
+        };
+        self.headers.set_header("ETag".to_owned(), etag);
+
+        self
+    }
+
+    pub fn set_last_modified(&mut self, time: SystemTime) -> &mut Self {
+        self.headers
+            .set_header("Last-Modified".to_owned(), format_http_date(time));
+
+        self
+    }
+
+    // a 304 keeps the validators but drops the content, a 412 is left to the caller
+    fn answer_precondition(&mut self, status: HttpStatusCode) -> HttpStatusCode {
+        self.body.clear();
+        if status == HttpStatusCode::Code304 {
+            self.headers.remove_header("Content-Type");
+            self.headers.remove_header("Content-Length");
+        }
+        self.status = status.clone();
+
+        status
+    }
+
+    // sets the validators and checks them before the body is made, e.g.
+    // `if let Some(status) = res.preconditions(req, Some("\"v2\""), None) { return Ok(status) }`
+    pub fn preconditions(
+        &mut self,
+        req: &Request,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Option<HttpStatusCode> {
+        if let Some(etag) = etag {
+            self.set_etag(etag);
+        }
+        if let Some(last_modified) = last_modified {
+            self.set_last_modified(last_modified);
+        }
+
+        let etag = self.headers.get_header("ETag").cloned();
+        let status = req.check_preconditions(etag.as_deref(), last_modified)?;
+
+        Some(self.answer_precondition(status))
+    }
+}
+
+impl ConditionalGet {
+    pub fn new() -> ConditionalGet {
+        ConditionalGet
+    }
+}
+
+// tags successful GET and HEAD responses that don't have an ETag yet with a hash of
+// their body, then answers 304 or 412 from the validators the response ended up with
+impl Middleware for ConditionalGet {
+    fn after(&self, req: &Request, res: &mut Response) {
+        if !matches!(req.method, Method::Get | Method::Head)
+            || res.status != HttpStatusCode::Code200
+        {
+            return;
+        }
+
+        if res.headers.get_header("ETag").is_none() {
+            let hash = sha256(&res.body);
+            res.set_etag(&base64::encode(&hash[..16], true));
+        }
+
+        let etag = res.headers.get_header("ETag").cloned();
+        let last_modified = res
+            .headers
+            .get_header("Last-Modified")
+            .and_then(|date| parse_http_date(date));
+
+        if let Some(status) = req.check_preconditions(etag.as_deref(), last_modified) {
+            res.answer_precondition(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request {
+            method,
+            ..Default::default()
+        };
+        for (name, value) in headers {
+            req.headers.set_header(*name, *value);
+        }
+
+        req
+    }
+
+    fn check(req: &Request, etag: Option<&str>, modified: Option<SystemTime>) -> Option<u16> {
+        req.check_preconditions(etag, modified)
+            .map(|status| status.as_u16())
+    }
+
+    #[test]
+    fn it_compares_etags() {
+        assert_eq!(
+            parse_etags(r#"W/"a", "b,c" ,"", junk"#),
+            vec![r#"W/"a""#, r#""b,c""#, r#""""#]
+        );
+
+        assert!(etag_matches(r#""a", "b""#, Some(r#""b""#), false));
+        assert!(!etag_matches(r#"W/"b""#, Some(r#""b""#), false));
+        assert!(etag_matches(r#"W/"b""#, Some(r#""b""#), true));
+        assert!(etag_matches(r#""b""#, Some(r#"W/"b""#), true));
+        assert!(!etag_matches(r#""a""#, Some(r#""b""#), true));
+        assert!(etag_matches("*", Some(r#""b""#), false));
+        assert!(!etag_matches("*", None, false));
+        assert!(!etag_matches(r#""b""#, None, true));
+        assert!(etag_matches(r#""b-gzip""#, Some(r#""b""#), true));
+        assert!(etag_matches(r#"W/"b-deflate""#, Some(r#""b""#), true));
+        assert!(!etag_matches(r#""b-gzip""#, Some(r#""b""#), false));
+        assert!(!etag_matches(r#""-gzip""#, Some(r#""""#), true));
+    }
+
+    #[test]
+    fn it_evaluates_preconditions() {
+        let etag = Some(r#""v2""#);
+        let modified = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let earlier = "Sun, 06 Nov 1994 08:49:36 GMT";
+
+        assert_eq!(
+            check(&request(Method::Get, &[]), etag, Some(modified)),
+            None
+        );
+
+        assert_eq!(
+            check(
+                &request(Method::Get, &[("If-None-Match", r#"W/"v2""#)]),
+                etag,
+                None
+            ),
+            Some(304)
+        );
+        assert_eq!(
+            check(
+                &request(Method::Get, &[("If-None-Match", r#""v1""#)]),
+                etag,
+                None
+            ),
+            None
+        );
+        assert_eq!(
+            check(&request(Method::Put, &[("If-None-Match", "*")]), etag, None),
+            Some(412)
+        );
+        assert_eq!(
+            check(&request(Method::Put, &[("If-None-Match", "*")]), None, None),
+            None
+        );
+
+        assert_eq!(
+            check(
+                &request(Method::Get, &[("If-Modified-Since", date)]),
+                None,
+                Some(modified)
+            ),
+            Some(304)
+        );
+        assert_eq!(
+            check(
+                &request(Method::Get, &[("If-Modified-Since", earlier)]),
+                None,
+                Some(modified)
+            ),
+            None
+        );
+        assert_eq!(
+            check(
+                &request(Method::Get, &[("If-Modified-Since", "garbage")]),
+                None,
+                Some(modified)
+            ),
+            None
+        );
+        assert_eq!(
+            check(
+                &request(Method::Put, &[("If-Modified-Since", date)]),
+                None,
+                Some(modified)
+            ),
+            None
+        );
+        // If-None-Match takes precedence over If-Modified-Since
+        assert_eq!(
+            check(
+                &request(
+                    Method::Get,
+                    &[("If-None-Match", r#""v1""#), ("If-Modified-Since", date)]
+                ),
+                etag,
+                Some(modified)
+            ),
+            None
+        );
+
+        assert_eq!(
+            check(
+                &request(Method::Put, &[("If-Match", r#""v2""#)]),
+                etag,
+                None
+            ),
+            None
+        );
+        assert_eq!(
+            check(
+                &request(Method::Put, &[("If-Match", r#"W/"v2""#)]),
+                etag,
+                None
+            ),
+            Some(412)
+        );
+        assert_eq!(
+            check(&request(Method::Put, &[("If-Match", "*")]), None, None),
+            Some(412)
+        );
+
+        assert_eq!(
+            check(
+                &request(Method::Put, &[("If-Unmodified-Since", date)]),
+                None,
+                Some(modified)
+            ),
+            None
+        );
+        assert_eq!(
+            check(
+                &request(Method::Put, &[("If-Unmodified-Since", earlier)]),
+                None,
+                Some(modified)
+            ),
+            Some(412)
+        );
+        // If-Match takes precedence over If-Unmodified-Since
+        assert_eq!(
+            check(
+                &request(
+                    Method::Put,
+                    &[("If-Match", r#""v2""#), ("If-Unmodified-Since", earlier)]
+                ),
+                etag,
+                Some(modified)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn it_answers_conditional_requests() {
+        let req = request(Method::Get, &[("If-None-Match", r#""v2""#)]);
+        let mut res = test_response();
+        res.text("expensive");
+        assert_eq!(
+            res.preconditions(&req, Some("v2"), Some(UNIX_EPOCH)),
+            Some(HttpStatusCode::Code304)
+        );
+        assert!(res.body.is_empty());
+        assert_eq!(
+            res.headers.get_header("ETag").map(String::as_str),
+            Some(r#""v2""#)
+        );
+        assert_eq!(
+            res.headers.get_header("Last-Modified").map(String::as_str),
+            Some("Thu, 01 Jan 1970 00:00:00 GMT")
+        );
+        assert_eq!(res.headers.get_header("Content-Type"), None);
+
+        let mut res = test_response();
+        res.text("hello");
+        ConditionalGet::new().after(&request(Method::Get, &[]), &mut res);
+        let etag = res.headers.get_header("ETag").cloned().unwrap();
+        assert_eq!(res.status, HttpStatusCode::Code200);
+        assert_eq!(res.body, b"hello");
+
+        let req = request(Method::Get, &[("If-None-Match", &etag)]);
+        let mut res = test_response();
+        res.text("hello");
+        ConditionalGet::new().after(&req, &mut res);
+        assert_eq!(res.status, HttpStatusCode::Code304);
+        assert!(res.body.is_empty());
+
+        let mut res = test_response();
+        res.text("changed");
+        ConditionalGet::new().after(&req, &mut res);
+        assert_eq!(res.status, HttpStatusCode::Code200);
+        assert_ne!(res.headers.get_header("ETag"), Some(&etag));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::types::{Connection, Duplex, ListenAddr, Listener, Transport};
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+// a udp socket or a tcp one that was never told to listen would only fail once accepted on,
+// so that's tried straight away; a connection that was already waiting is handed back
+#[cfg(unix)]
+fn probe_accept(accepted: io::Result<Connection>) -> io::Result<Option<Connection>> {
+    match accepted {
+        Ok(connection) => Ok(Some(connection)),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        // the peer gave up before it was accepted, which still means it's listening
+        Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => Ok(None),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The file descriptor isn't a listening stream socket",
+        )),
+    }
+}
+
+// `pid` and `fds` are LISTEN_PID and LISTEN_FDS, which have to name this process and pass a
+// single socket; with more than one they're left for the caller to adopt with `from_fd`
+#[cfg(unix)]
+fn check_listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> io::Result<()> {
+    let not_activated = |reason: &str| io::Error::new(io::ErrorKind::NotFound, reason);
+
+    let pid = pid.ok_or_else(|| not_activated("LISTEN_PID isn't set"))?;
+    if pid.trim().parse::<u32>().ok() != Some(own_pid) {
+        return Err(not_activated("LISTEN_PID is for another process"));
+    }
+    let fds = fds
+        .and_then(|fds| fds.trim().parse::<RawFd>().ok())
+        .unwrap_or(0);
+    if fds < 1 {
+        return Err(not_activated("No file descriptors were passed"));
+    }
+    if fds > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} sockets were passed, only one can be listened on", fds),
+        ));
+    }
+
+    Ok(())
+}
+
+impl Listener {
+    // a socket file left behind by a server that didn't shut down cleanly is replaced,
+    // one that still has a server behind it or a path that isn't a socket is left alone
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(path: &Path, mode: u32) -> io::Result<Listener> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("`{}` exists and isn't a socket", path.display()),
+                ));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("`{}` is already being listened on", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+
+        Ok(Listener::Unix(listener, Some(path.to_owned())))
+    }
+
+    // a socket that is already bound and listening, tcp or unix, e.g. one passed down
+    // by a process manager; the socket file of a unix one is left for its owner to remove.
+    // checking it may accept a connection that was already waiting, which is returned
+    // to be answered first
+    #[cfg(unix)]
+    pub(crate) fn from_fd(fd: OwnedFd) -> io::Result<(Listener, Option<Connection>)> {
+        let tcp = TcpListener::from(fd);
+        let listener = if tcp.local_addr().is_ok() {
+            Listener::Tcp(tcp)
+        } else {
+            let unix = UnixListener::from(OwnedFd::from(tcp));
+            if unix.local_addr().is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The file descriptor isn't a tcp or unix socket",
+                ));
+            }
+            Listener::Unix(unix, None)
+        };
+
+        // whoever made the socket may have left it non-blocking, and some platforms pass
+        // that on to the accepted connection
+        let pending = match &listener {
+            Listener::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                let pending = probe_accept(listener.accept().map(|(s, _)| Connection::Tcp(s)));
+                listener.set_nonblocking(false)?;
+                pending?
+            }
+            Listener::Unix(listener, _) => {
+                listener.set_nonblocking(true)?;
+                let pending = probe_accept(listener.accept().map(|(s, _)| Connection::Unix(s)));
+                listener.set_nonblocking(false)?;
+                pending?
+            }
+        };
+        match &pending {
+            Some(Connection::Tcp(stream)) => stream.set_nonblocking(false)?,
+            Some(Connection::Unix(stream)) => stream.set_nonblocking(false)?,
+            None => {}
+        }
+
+        Ok((listener, pending))
+    }
+
+    // the socket passed with systemd's socket activation protocol; the variables are left
+    // as they are, a process that starts others should clear them before it does
+    #[cfg(unix)]
+    pub(crate) fn from_listen_fds() -> io::Result<(Listener, Option<Connection>)> {
+        // the first passed descriptor, always right after stdin, stdout and stderr
+        const LISTEN_FDS_START: RawFd = 3;
+
+        let pid = std::env::var("LISTEN_PID").ok();
+        let fds = std::env::var("LISTEN_FDS").ok();
+        check_listen_fds(pid.as_deref(), fds.as_deref(), std::process::id())?;
+
+        // SAFETY: LISTEN_PID names this process, so the manager that set the variables
+        // passed it this descriptor; it's only ever claimed here
+        let inherited = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) };
+        // it's passed without close-on-exec, which the duplicate gets set; this way it
+        // isn't leaked into processes the handlers start
+        let fd = inherited.try_clone()?;
+        drop(inherited);
+
+        Listener::from_fd(fd)
+    }
+
+    pub(crate) fn listen_addr(&self) -> Option<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => {
+                let mut addr = listener.local_addr().ok()?;
+                // a server listening on every interface can still be reached over loopback
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(match addr.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                Some(ListenAddr::Tcp(addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let addr = listener.local_addr().ok()?;
+                Some(ListenAddr::Unix(addr.as_pathname()?.to_owned()))
+            }
+        }
+    }
+
+    pub(crate) fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => Ok(Connection::Tcp(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Connection::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, Some(path)) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl ListenAddr {
+    // the connection is dropped straight away, all that matters is that it was accepted
+    pub(crate) fn poke(&self) -> io::Result<()> {
+        match self {
+            ListenAddr::Tcp(addr) => TcpStream::connect(addr).map(drop),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => UnixStream::connect(path).map(drop),
+        }
+    }
+}
+
+impl Connection {
+    pub(crate) fn into_transport(self) -> Box<dyn Transport> {
+        match self {
+            Connection::Tcp(stream) => Box::new(stream),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Box::new(stream),
+        }
+    }
+}
+
+impl Transport for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpStream::local_addr(self).ok()
+    }
+}
+
+// unix sockets have no address worth reporting
+#[cfg(unix)]
+impl Transport for UnixStream {}
+
+impl<R, W> Duplex<R, W> {
+    pub fn new(input: R, output: W) -> Duplex<R, W> {
+        Duplex { input, output }
+    }
+}
+
+impl<R, W> Read for Duplex<R, W>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl<R, W> Write for Duplex<R, W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<R, W> Transport for Duplex<R, W>
+where
+    R: Read + Send,
+    W: Write + Send,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn it_checks_socket_activation_variables() {
+        let kind = |pid, fds| check_listen_fds(pid, fds, 42).map_err(|err| err.kind());
+
+        assert_eq!(kind(Some("42"), Some("1")), Ok(()));
+        assert_eq!(kind(Some(" 42\n"), Some("1 ")), Ok(()));
+        // meant for some other process
+        assert_eq!(kind(Some("1"), Some("1")), Err(io::ErrorKind::NotFound));
+        assert_eq!(kind(None, Some("1")), Err(io::ErrorKind::NotFound));
+        assert_eq!(kind(Some("42"), None), Err(io::ErrorKind::NotFound));
+        assert_eq!(kind(Some("42"), Some("0")), Err(io::ErrorKind::NotFound));
+        assert_eq!(
+            kind(Some("42"), Some("2")),
+            Err(io::ErrorKind::InvalidInput)
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use crate::date::format_http_date;
+use crate::form::percent_decode;
+use crate::types::{Cookie, CookieJar, HeaderMethods, Request, Response, SameSite};
+
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// anything that could end the pair or the header early is percent-encoded, and so is `%`
+// itself so the jar can decode values back to what was set
+fn encode(input: &str, allowed: fn(u8) -> bool) -> Cow<'_, str> {
+    if input.bytes().all(|byte| byte != b'%' && allowed(byte)) {
+        return Cow::Borrowed(input);
+    }
+
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte != b'%' && allowed(byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    Cow::Owned(encoded)
+}
+
+fn is_token(byte: u8) -> bool {
+    byte.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&byte)
+}
+
+fn is_cookie_octet(byte: u8) -> bool {
+    byte.is_ascii_graphic() && !b"\",;\\".contains(&byte)
+}
+
+// path and domain may hold anything but controls and `;`
+fn is_attribute_value(byte: u8) -> bool {
+    (byte == b' ' || byte.is_ascii_graphic()) && byte != b';'
+}
+
+impl Cookie {
+    pub fn new<N, V>(name: N, value: V) -> Cookie
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn path<S>(mut self, path: S) -> Cookie
+    where
+        S: Into<String>,
+    {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain<S>(mut self, domain: S) -> Cookie
+    where
+        S: Into<String>,
+    {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{}",
+            match self {
+                SameSite::Strict => "Strict",
+                SameSite::Lax => "Lax",
+                SameSite::None => "None",
+            }
+        )
+    }
+}
+
+impl Display for Cookie {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{}={}",
+            encode(&self.name, is_token),
+            encode(&self.value, is_cookie_octet)
+        )?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", encode(path, is_attribute_value))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", encode(domain, is_attribute_value))?;
+        }
+        if let Some(max_age) = &self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        // browsers drop SameSite=None cookies that aren't also secure
+        if self.secure || self.same_site == Some(SameSite::None) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = &self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CookieJar {
+    pub(crate) fn parse(header: &str) -> CookieJar {
+        let mut cookies = Vec::new();
+
+        for pair in header.split(';') {
+            if let Some((name, value)) = pair.split_once('=') {
+                let name = name.trim();
+                if name.is_empty() {
+                    continue;
+                }
+
+                let value = value.trim();
+                let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                    Some(unquoted) => unquoted,
+                    None => value,
+                };
+
+                cookies.push((percent_decode(name, false), percent_decode(value, false)));
+            }
+        }
+
+        CookieJar(cookies)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Request {
+    pub fn cookies(&self) -> CookieJar {
+        match self.headers.get_header("Cookie") {
+            Some(header) => CookieJar::parse(header),
+            None => CookieJar::default(),
+        }
+    }
+}
+
+impl Response {
+    // a later cookie with the same name, path and domain replaces an earlier one
+    pub fn set_cookie(&mut self, cookie: Cookie) -> &mut Self {
+        self.cookies.retain(|existing| {
+            existing.name != cookie.name
+                || existing.path != cookie.path
+                || existing.domain != cookie.domain
+        });
+        self.cookies.push(cookie);
+
+        self
+    }
+
+    // the path and domain have to match the ones the cookie was set with for browsers to remove it
+    pub fn remove_cookie(&mut self, cookie: Cookie) -> &mut Self {
+        let mut cookie = cookie.max_age(Duration::ZERO).expires(UNIX_EPOCH);
+        cookie.value.clear();
+
+        self.set_cookie(cookie)
+    }
+
+    pub fn cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_cookie_headers() {
+        let mut req = Request::default();
+        assert!(req.cookies().is_empty());
+
+        req.headers.set_header(
+            "Cookie",
+            "sid=abc123; theme=\"dark\"; empty=; =nameless; sid=second",
+        );
+        let jar = req.cookies();
+
+        assert_eq!(jar.get("sid"), Some("abc123"));
+        assert_eq!(jar.get("theme"), Some("dark"));
+        assert_eq!(jar.get("empty"), Some(""));
+        assert_eq!(jar.get("missing"), None);
+        assert_eq!(jar.len(), 4);
+    }
+
+    #[test]
+    fn it_formats_set_cookie() {
+        assert_eq!(Cookie::new("a", "b").to_string(), "a=b");
+        assert_eq!(
+            Cookie::new("sid", "xyz")
+                .path("/")
+                .domain("example.com")
+                .max_age(Duration::from_secs(3600))
+                .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+                .secure(true)
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .to_string(),
+            "sid=xyz; Path=/; Domain=example.com; Max-Age=3600; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(
+            Cookie::new("a", "b").same_site(SameSite::None).to_string(),
+            "a=b; Secure; SameSite=None"
+        );
+    }
+
+    #[test]
+    fn it_encodes_hostile_cookies() {
+        let cookie = Cookie::new("a;b", "x; Domain=evil.test\r\nSet-Cookie: admin=1")
+            .path("/;\r\nX: y")
+            .domain("example.com; HttpOnly");
+        let header = cookie.to_string();
+        assert_eq!(
+            header,
+            "a%3Bb=x%3B%20Domain=evil.test%0D%0ASet-Cookie:%20admin=1; Path=/%3B%0D%0AX: y; Domain=example.com%3B HttpOnly"
+        );
+        assert!(!header.contains(['\r', '\n']));
+
+        let jar = CookieJar::parse(header.split(';').next().unwrap());
+        assert_eq!(
+            jar.get("a;b"),
+            Some("x; Domain=evil.test\r\nSet-Cookie: admin=1")
+        );
+        assert_eq!(Cookie::new("rate", "100%").to_string(), "rate=100%25");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::base64;
+use crate::crypto::{constant_time_eq, hmac_sha256, open, random_bytes, seal};
+use crate::types::{Cookie, CookieJar, Key, Keyring, PrivateJar, SignedJar};
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::io;
+
+const NONCE_LEN: usize = 12;
+
+impl Key {
+    // derives independent signing and encryption keys, so the secret should be at least 32 random bytes
+    pub fn derive(secret: &[u8]) -> Key {
+        Key {
+            signing: hmac_sha256(secret, b"rebar cookie signing"),
+            encryption: hmac_sha256(secret, b"rebar cookie encryption"),
+        }
+    }
+
+    pub fn generate() -> io::Result<Key> {
+        Ok(Key {
+            signing: random_bytes()?,
+            encryption: random_bytes()?,
+        })
+    }
+
+    fn mac(&self, name: &str, value: &str) -> [u8; 32] {
+        hmac_sha256(&self.signing, format!("{}={}", name, value).as_bytes())
+    }
+}
+
+// never print the key material
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "Key {{ .. }}")
+    }
+}
+
+impl Keyring {
+    pub fn new(key: Key) -> Keyring {
+        Keyring { keys: vec![key] }
+    }
+
+    pub fn with_previous(mut self, key: Key) -> Keyring {
+        self.keys.push(key);
+        self
+    }
+
+    fn primary(&self) -> &Key {
+        &self.keys[0]
+    }
+
+    // the value is kept readable but prefixed with a signature over the name and value
+    pub fn sign(&self, mut cookie: Cookie) -> Cookie {
+        let mac = self.primary().mac(&cookie.name, &cookie.value);
+        cookie.value = format!("{}.{}", base64::encode(&mac, true), cookie.value);
+
+        cookie
+    }
+
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (mac, value) = signed.split_once('.')?;
+        let mac = base64::decode(mac, true)?;
+
+        self.keys
+            .iter()
+            .any(|key| constant_time_eq(&key.mac(name, value), &mac))
+            .then(|| value.to_owned())
+    }
+
+    // the name is authenticated too, so a value can't be moved to another cookie
+    pub fn encrypt(&self, mut cookie: Cookie) -> io::Result<Cookie> {
+        let nonce = random_bytes::<NONCE_LEN>()?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(seal(
+            &self.primary().encryption,
+            &nonce,
+            cookie.name.as_bytes(),
+            cookie.value.as_bytes(),
+        ));
+        cookie.value = base64::encode(&sealed, true);
+
+        Ok(cookie)
+    }
+
+    pub fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+        let sealed = base64::decode(encrypted, true)?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().ok()?;
+
+        self.keys
+            .iter()
+            .find_map(|key| open(&key.encryption, &nonce, name.as_bytes(), sealed))
+            .and_then(|plaintext| String::from_utf8(plaintext).ok())
+    }
+}
+
+impl CookieJar {
+    pub fn signed<'a>(&'a self, keys: &'a Keyring) -> SignedJar<'a> {
+        SignedJar { jar: self, keys }
+    }
+
+    pub fn private<'a>(&'a self, keys: &'a Keyring) -> PrivateJar<'a> {
+        PrivateJar { jar: self, keys }
+    }
+}
+
+impl SignedJar<'_> {
+    // cookies that are missing or were tampered with both come back as `None`
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.jar
+            .get(name)
+            .and_then(|value| self.keys.verify(name, value))
+    }
+}
+
+impl PrivateJar<'_> {
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.jar
+            .get(name)
+            .and_then(|value| self.keys.decrypt(name, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jar(name: &str, value: &str) -> CookieJar {
+        CookieJar(vec![(name.to_owned(), value.to_owned())])
+    }
+
+    #[test]
+    fn it_signs_cookies() {
+        let keys = Keyring::new(Key::derive(b"an example secret that is long enough"));
+
+        let cookie = keys.sign(Cookie::new("user", "42.admin").path("/"));
+        assert!(cookie.value.ends_with(".42.admin"));
+        assert_eq!(cookie.path.as_deref(), Some("/"));
+
+        assert_eq!(
+            jar("user", &cookie.value).signed(&keys).get("user"),
+            Some("42.admin".to_owned())
+        );
+
+        let forged = cookie.value.replace("42", "43");
+        assert_eq!(jar("user", &forged).signed(&keys).get("user"), None);
+        assert_eq!(jar("other", &cookie.value).signed(&keys).get("other"), None);
+        assert_eq!(jar("user", "42.admin").signed(&keys).get("user"), None);
+    }
+
+    #[test]
+    fn it_encrypts_cookies() {
+        let keys = Keyring::new(Key::generate().unwrap());
+
+        let cookie = keys
+            .encrypt(Cookie::new("flash", "Saved; all good"))
+            .unwrap();
+        assert!(!cookie.value.contains("Saved"));
+        assert_ne!(
+            keys.encrypt(Cookie::new("flash", "Saved; all good"))
+                .unwrap(),
+            cookie
+        );
+
+        assert_eq!(
+            jar("flash", &cookie.value).private(&keys).get("flash"),
+            Some("Saved; all good".to_owned())
+        );
+        assert_eq!(
+            jar("other", &cookie.value).private(&keys).get("other"),
+            None
+        );
+        assert_eq!(jar("flash", "garbage").private(&keys).get("flash"), None);
+        assert_eq!(jar("flash", "").private(&keys).get("flash"), None);
+    }
+
+    #[test]
+    fn it_rotates_keys() {
+        let old = Keyring::new(Key::derive(b"old secret"));
+        let signed = old.sign(Cookie::new("a", "1"));
+        let encrypted = old.encrypt(Cookie::new("b", "2")).unwrap();
+
+        let rotated =
+            Keyring::new(Key::derive(b"new secret")).with_previous(Key::derive(b"old secret"));
+        assert_eq!(rotated.verify("a", &signed.value), Some("1".to_owned()));
+        assert_eq!(rotated.decrypt("b", &encrypted.value), Some("2".to_owned()));
+
+        let fresh = Keyring::new(Key::derive(b"new secret"));
+        assert_eq!(fresh.verify("a", &signed.value), None);
+        assert_eq!(fresh.decrypt("b", &encrypted.value), None);
+
+        // new cookies are always made with the primary key
+        assert_eq!(
+            fresh.verify("a", &rotated.sign(Cookie::new("a", "1")).value),
+            Some("1".to_owned())
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use crate::types::{
+    Cors, CorsOrigins, HeaderMethods, HttpStatusCode, Method, Middleware, Request, Response,
+};
+
+use std::time::Duration;
+
+impl Cors {
+    // allows no origins until some are added, with the methods a plain form could send anyway
+    pub fn new() -> Cors {
+        Cors {
+            origins: CorsOrigins::List(vec![]),
+            methods: vec!["GET".to_owned(), "HEAD".to_owned(), "POST".to_owned()],
+            headers: vec![],
+            exposed_headers: vec![],
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    // can't be combined with credentials, any site could then read responses as the user
+    pub fn allow_any_origin(mut self) -> Cors {
+        assert!(
+            !self.credentials,
+            "Credentials can't be allowed for any origin, list the origins instead"
+        );
+        self.origins = CorsOrigins::Any;
+        self
+    }
+
+    // exact matches like `https://example.com`, without a path or trailing slash
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        let origin = origin.trim_end_matches('/').to_owned();
+        match &mut self.origins {
+            CorsOrigins::List(origins) => origins.push(origin),
+            _ => self.origins = CorsOrigins::List(vec![origin]),
+        }
+        self
+    }
+
+    pub fn allow_origins(self, origins: &[&str]) -> Cors {
+        origins
+            .iter()
+            .fold(self, |cors, origin| cors.allow_origin(origin))
+    }
+
+    pub fn allow_origin_fn<P>(mut self, predicate: P) -> Cors
+    where
+        P: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.origins = CorsOrigins::Predicate(Box::new(predicate));
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: &[Method]) -> Cors {
+        self.methods = methods.iter().map(Method::to_string).collect();
+        self
+    }
+
+    // `*` allows whatever headers the preflight asks for
+    pub fn allow_headers(mut self, headers: &[&str]) -> Cors {
+        self.headers = headers.iter().map(|header| header.to_lowercase()).collect();
+        self
+    }
+
+    pub fn expose_headers(mut self, headers: &[&str]) -> Cors {
+        self.exposed_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    pub fn allow_credentials(mut self, allow: bool) -> Cors {
+        assert!(
+            !allow || !matches!(self.origins, CorsOrigins::Any),
+            "Credentials can't be allowed for any origin, list the origins instead"
+        );
+        self.credentials = allow;
+        self
+    }
+
+    // how long browsers may cache a preflight answer
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn is_allowed(&self, origin: &str) -> bool {
+        match &self.origins {
+            CorsOrigins::Any => true,
+            CorsOrigins::List(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+            CorsOrigins::Predicate(predicate) => predicate(origin),
+        }
+    }
+
+    fn is_preflight(req: &Request) -> bool {
+        req.method == Method::Options
+            && req.headers.get_header("Origin").is_some()
+            && req
+                .headers
+                .get_header("Access-Control-Request-Method")
+                .is_some()
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    // preflights are answered here so they never reach the handler
+    fn before(&self, req: &mut Request, res: &mut Response) -> Option<HttpStatusCode> {
+        if !Cors::is_preflight(req) {
+            return None;
+        }
+
+        res.add_vary("Access-Control-Request-Method")
+            .add_vary("Access-Control-Request-Headers");
+
+        // a disallowed origin just doesn't get the headers, the browser does the refusing
+        let origin = req.headers.get_header("Origin")?;
+        if self.is_allowed(origin) {
+            res.headers.set_header(
+                "Access-Control-Allow-Methods".to_owned(),
+                self.methods.join(", "),
+            );
+
+            let requested = req.headers.get_header("Access-Control-Request-Headers");
+            let allowed = match requested {
+                Some(requested) if self.headers.iter().any(|header| header == "*") => {
+                    Some(requested.clone())
+                }
+                _ if !self.headers.is_empty() => Some(self.headers.join(", ")),
+                _ => None,
+            };
+            if let Some(allowed) = allowed {
+                res.headers
+                    .set_header("Access-Control-Allow-Headers".to_owned(), allowed);
+            }
+
+            if let Some(max_age) = self.max_age {
+                res.headers.set_header(
+                    "Access-Control-Max-Age".to_owned(),
+                    max_age.as_secs().to_string(),
+                );
+            }
+        }
+
+        res.body.clear();
+        res.headers.remove_header("Content-Type");
+
+        Some(HttpStatusCode::Code204)
+    }
+
+    fn after(&self, req: &Request, res: &mut Response) {
+        // the answer depends on the origin unless every origin gets the same `*`
+        let wildcard = matches!(self.origins, CorsOrigins::Any);
+        if !wildcard {
+            res.add_vary("Origin");
+        }
+
+        let origin = match req.headers.get_header("Origin") {
+            Some(origin) if self.is_allowed(origin) => origin,
+            _ => return,
+        };
+
+        // browsers refuse `*` on credentialed requests, so a listed origin is echoed instead
+        let allow_origin = if wildcard { "*" } else { origin };
+        res.headers
+            .set_header("Access-Control-Allow-Origin", allow_origin);
+
+        if self.credentials {
+            res.headers
+                .set_header("Access-Control-Allow-Credentials", "true");
+        }
+        if !self.exposed_headers.is_empty() && !Cors::is_preflight(req) {
+            res.headers.set_header(
+                "Access-Control-Expose-Headers".to_owned(),
+                self.exposed_headers.join(", "),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+
+    // runs a request through the middleware the way `dispatch` would
+    fn run(
+        cors: &Cors,
+        method: Method,
+        headers: &[(&str, &str)],
+    ) -> (Option<HttpStatusCode>, Response) {
+        let mut req = Request {
+            method,
+            ..Default::default()
+        };
+        for (name, value) in headers {
+            req.headers.set_header(*name, *value);
+        }
+
+        let mut res = test_response();
+        let answered = cors.before(&mut req, &mut res);
+        cors.after(&req, &mut res);
+
+        (answered, res)
+    }
+
+    fn header<'a>(res: &'a Response, name: &str) -> Option<&'a str> {
+        res.headers.get_header(name).map(String::as_str)
+    }
+
+    #[test]
+    fn it_answers_preflights() {
+        let cors = Cors::new()
+            .allow_origins(&["https://app.example.com", "https://admin.example.com/"])
+            .allow_methods(&[Method::Get, Method::Put, Method::Delete])
+            .allow_headers(&["Content-Type", "X-Requested-With"])
+            .max_age(Duration::from_secs(600));
+
+        let (answered, res) = run(
+            &cors,
+            Method::Options,
+            &[
+                ("Origin", "https://admin.example.com"),
+                ("Access-Control-Request-Method", "PUT"),
+            ],
+        );
+        assert_eq!(answered, Some(HttpStatusCode::Code204));
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Origin"),
+            Some("https://admin.example.com")
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Methods"),
+            Some("GET, PUT, DELETE")
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Headers"),
+            Some("content-type, x-requested-with")
+        );
+        assert_eq!(header(&res, "Access-Control-Max-Age"), Some("600"));
+        assert_eq!(
+            header(&res, "Vary"),
+            Some("Access-Control-Request-Method, Access-Control-Request-Headers, Origin")
+        );
+
+        let (answered, res) = run(
+            &cors,
+            Method::Options,
+            &[
+                ("Origin", "https://evil.example.com"),
+                ("Access-Control-Request-Method", "PUT"),
+            ],
+        );
+        assert_eq!(answered, Some(HttpStatusCode::Code204));
+        assert_eq!(header(&res, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&res, "Access-Control-Allow-Methods"), None);
+
+        // plain OPTIONS requests are left to the handler
+        let (answered, _) = run(
+            &cors,
+            Method::Options,
+            &[("Origin", "https://app.example.com")],
+        );
+        assert_eq!(answered, None);
+
+        let mirror = Cors::new().allow_any_origin().allow_headers(&["*"]);
+        let (_, res) = run(
+            &mirror,
+            Method::Options,
+            &[
+                ("Origin", "https://a.example"),
+                ("Access-Control-Request-Method", "POST"),
+                ("Access-Control-Request-Headers", "x-one, x-two"),
+            ],
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Headers"),
+            Some("x-one, x-two")
+        );
+    }
+
+    #[test]
+    fn it_sets_headers_on_simple_requests() {
+        let any = Cors::new()
+            .allow_any_origin()
+            .expose_headers(&["X-Total-Count"]);
+        let (answered, res) = run(&any, Method::Get, &[("Origin", "https://a.example")]);
+        assert_eq!(answered, None);
+        assert_eq!(header(&res, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            header(&res, "Access-Control-Expose-Headers"),
+            Some("X-Total-Count")
+        );
+        assert_eq!(header(&res, "Vary"), None);
+
+        let credentialed = Cors::new()
+            .allow_origin("https://a.example")
+            .allow_credentials(true);
+        let (_, res) = run(
+            &credentialed,
+            Method::Get,
+            &[("Origin", "https://a.example")],
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Origin"),
+            Some("https://a.example")
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(header(&res, "Vary"), Some("Origin"));
+
+        let subdomains = Cors::new().allow_origin_fn(|origin| origin.ends_with(".example.com"));
+        let (_, res) = run(
+            &subdomains,
+            Method::Post,
+            &[("Origin", "https://shop.example.com")],
+        );
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Origin"),
+            Some("https://shop.example.com")
+        );
+
+        // responses that don't allow the origin still vary on it for caches
+        let (_, res) = run(
+            &subdomains,
+            Method::Post,
+            &[("Origin", "https://example.org")],
+        );
+        assert_eq!(header(&res, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&res, "Vary"), Some("Origin"));
+        let (_, res) = run(&subdomains, Method::Get, &[]);
+        assert_eq!(header(&res, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Credentials can't be allowed for any origin")]
+    fn it_refuses_credentials_for_any_origin() {
+        Cors::new().allow_any_origin().allow_credentials(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Credentials can't be allowed for any origin")]
+    fn it_refuses_any_origin_with_credentials() {
+        Cors::new().allow_credentials(true).allow_any_origin();
+    }
+}
@@ -0,0 +1,451 @@
+#[cfg(unix)]
+use std::fs::File;
+use std::io;
+#[cfg(unix)]
+use std::io::Read;
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                block[i * 4],
+                block[i * 4 + 1],
+                block[i * 4 + 2],
+                block[i * 4 + 3],
+            ]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (chunk, word) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+pub(crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block_key = [0u8; 64];
+    if key.len() > 64 {
+        block_key[..32].copy_from_slice(&sha256(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = block_key.iter().map(|b| b ^ 0x36).collect::<Vec<_>>();
+    inner.extend_from_slice(data);
+
+    let mut outer = block_key.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>();
+    outer.extend_from_slice(&sha256(&inner));
+
+    sha256(&outer)
+}
+
+// compares in time that only depends on the length, so secrets can't be guessed byte by byte
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// keys, session ids and csrf secrets come from here, so there's nothing safe to fall back to
+// when the os can't provide randomness
+pub(crate) fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    fill_random(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+#[cfg(unix)]
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    File::open("/dev/urandom")?.read_exact(buf)
+}
+
+#[cfg(windows)]
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    // BCRYPT_USE_SYSTEM_PREFERRED_RNG, so no algorithm handle has to be opened first
+    const SYSTEM_PREFERRED_RNG: u32 = 0x2;
+
+    #[link(name = "bcrypt")]
+    extern "system" {
+        fn BCryptGenRandom(
+            algorithm: *mut std::ffi::c_void,
+            buf: *mut u8,
+            len: u32,
+            flags: u32,
+        ) -> i32;
+    }
+
+    for chunk in buf.chunks_mut(u32::MAX as usize) {
+        // SAFETY: the pointer and length describe `chunk`, which outlives the call
+        let status = unsafe {
+            BCryptGenRandom(
+                std::ptr::null_mut(),
+                chunk.as_mut_ptr(),
+                chunk.len() as u32,
+                SYSTEM_PREFERRED_RNG,
+            )
+        };
+        if status != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("BCryptGenRandom failed with status {:#x}", status),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn fill_random(_buf: &mut [u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "No source of os randomness on this platform",
+    ))
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
+    fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        s[a] = s[a].wrapping_add(s[b]);
+        s[d] = (s[d] ^ s[a]).rotate_left(16);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_left(12);
+        s[a] = s[a].wrapping_add(s[b]);
+        s[d] = (s[d] ^ s[a]).rotate_left(8);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_left(7);
+    }
+
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for i in 0..8 {
+        initial[4 + i] = le32(&key[i * 4..]);
+    }
+    initial[12] = counter;
+    for i in 0..3 {
+        initial[13 + i] = le32(&nonce[i * 4..]);
+    }
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut block = [0u8; 64];
+    for (i, chunk) in block.chunks_mut(4).enumerate() {
+        chunk.copy_from_slice(&state[i].wrapping_add(initial[i]).to_le_bytes());
+    }
+    block
+}
+
+fn chacha20_xor(key: &[u8; 32], counter: u32, nonce: &[u8; 12], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let block = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        for (byte, key_byte) in chunk.iter_mut().zip(block) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+fn poly1305(key: &[u8; 32], message: &[u8]) -> [u8; 16] {
+    const MASK: u32 = 0x3ffffff;
+
+    let r0 = le32(&key[0..]) & 0x3ffffff;
+    let r1 = (le32(&key[3..]) >> 2) & 0x3ffff03;
+    let r2 = (le32(&key[6..]) >> 4) & 0x3ffc0ff;
+    let r3 = (le32(&key[9..]) >> 6) & 0x3f03fff;
+    let r4 = (le32(&key[12..]) >> 8) & 0x00fffff;
+    let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+    let (mut h0, mut h1, mut h2, mut h3, mut h4) = (0u32, 0u32, 0u32, 0u32, 0u32);
+
+    for chunk in message.chunks(16) {
+        // full blocks get their high bit above the 128 bits, partial ones right after the data
+        let mut block = [0u8; 17];
+        block[..chunk.len()].copy_from_slice(chunk);
+        block[chunk.len()] = 1;
+
+        h0 += le32(&block[0..]) & MASK;
+        h1 += (le32(&block[3..]) >> 2) & MASK;
+        h2 += (le32(&block[6..]) >> 4) & MASK;
+        h3 += (le32(&block[9..]) >> 6) & MASK;
+        h4 += (le32(&block[12..]) >> 8) | (u32::from(block[16]) << 24);
+
+        let m = |a: u32, b: u32| u64::from(a) * u64::from(b);
+        let d0 = m(h0, r0) + m(h1, s4) + m(h2, s3) + m(h3, s2) + m(h4, s1);
+        let d1 = m(h0, r1) + m(h1, r0) + m(h2, s4) + m(h3, s3) + m(h4, s2);
+        let d2 = m(h0, r2) + m(h1, r1) + m(h2, r0) + m(h3, s4) + m(h4, s3);
+        let d3 = m(h0, r3) + m(h1, r2) + m(h2, r1) + m(h3, r0) + m(h4, s4);
+        let d4 = m(h0, r4) + m(h1, r3) + m(h2, r2) + m(h3, r1) + m(h4, r0);
+
+        let mut c = (d0 >> 26) as u32;
+        h0 = d0 as u32 & MASK;
+        let d1 = d1 + u64::from(c);
+        c = (d1 >> 26) as u32;
+        h1 = d1 as u32 & MASK;
+        let d2 = d2 + u64::from(c);
+        c = (d2 >> 26) as u32;
+        h2 = d2 as u32 & MASK;
+        let d3 = d3 + u64::from(c);
+        c = (d3 >> 26) as u32;
+        h3 = d3 as u32 & MASK;
+        let d4 = d4 + u64::from(c);
+        c = (d4 >> 26) as u32;
+        h4 = d4 as u32 & MASK;
+        h0 += c * 5;
+        c = h0 >> 26;
+        h0 &= MASK;
+        h1 += c;
+    }
+
+    let mut c = h1 >> 26;
+    h1 &= MASK;
+    h2 += c;
+    c = h2 >> 26;
+    h2 &= MASK;
+    h3 += c;
+    c = h3 >> 26;
+    h3 &= MASK;
+    h4 += c;
+    c = h4 >> 26;
+    h4 &= MASK;
+    h0 += c * 5;
+    c = h0 >> 26;
+    h0 &= MASK;
+    h1 += c;
+
+    // work out h - p and keep it if that didn't underflow
+    let mut g0 = h0.wrapping_add(5);
+    c = g0 >> 26;
+    g0 &= MASK;
+    let mut g1 = h1.wrapping_add(c);
+    c = g1 >> 26;
+    g1 &= MASK;
+    let mut g2 = h2.wrapping_add(c);
+    c = g2 >> 26;
+    g2 &= MASK;
+    let mut g3 = h3.wrapping_add(c);
+    c = g3 >> 26;
+    g3 &= MASK;
+    let g4 = h4.wrapping_add(c).wrapping_sub(1 << 26);
+
+    let select = (g4 >> 31).wrapping_sub(1);
+    h0 = (h0 & !select) | (g0 & select);
+    h1 = (h1 & !select) | (g1 & select);
+    h2 = (h2 & !select) | (g2 & select);
+    h3 = (h3 & !select) | (g3 & select);
+    h4 = (h4 & !select) | (g4 & select);
+
+    let words = [
+        h0 | (h1 << 26),
+        (h1 >> 6) | (h2 << 20),
+        (h2 >> 12) | (h3 << 14),
+        (h3 >> 18) | (h4 << 8),
+    ];
+
+    let mut tag = [0u8; 16];
+    let mut carry = 0u64;
+    for i in 0..4 {
+        let sum = u64::from(words[i]) + u64::from(le32(&key[16 + i * 4..])) + carry;
+        tag[i * 4..i * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+        carry = sum >> 32;
+    }
+    tag
+}
+
+fn aead_tag(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let mut poly_key = [0u8; 32];
+    poly_key.copy_from_slice(&chacha20_block(key, 0, nonce)[..32]);
+
+    let pad = |len: usize| vec![0u8; (16 - len % 16) % 16];
+    let mut mac_data = aad.to_vec();
+    mac_data.extend(pad(aad.len()));
+    mac_data.extend_from_slice(ciphertext);
+    mac_data.extend(pad(ciphertext.len()));
+    mac_data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    mac_data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+
+    poly1305(&poly_key, &mac_data)
+}
+
+// chacha20-poly1305 as specified in RFC 8439, returning the ciphertext followed by the tag
+pub(crate) fn seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut sealed = plaintext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut sealed);
+
+    let tag = aead_tag(key, nonce, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+pub(crate) fn open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < 16 {
+        return None;
+    }
+
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - 16);
+    if !constant_time_eq(&aead_tag(key, nonce, aad, ciphertext), tag) {
+        return None;
+    }
+
+    let mut plaintext = ciphertext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut plaintext);
+    Some(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn it_hashes() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(&[b'a'; 200])),
+            "c2a908d98f5df987ade41b5fce213067efbcc21ef2240212a41e54b5e7c28ae5"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(
+                b"key",
+                b"The quick brown fox jumps over the lazy dog"
+            )),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(&[b'k'; 100], b"data")),
+            "09380ee4b802da2363bc96e8e0d133ba275458ea8ddbc564f986fc12b31f8cb1"
+        );
+    }
+
+    #[test]
+    fn it_seals_and_opens() {
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = 0x80 + i as u8;
+        }
+        let nonce = [
+            0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+        // the ciphertext and tag from RFC 8439 section 2.8.2
+        let sealed = seal(&key, &nonce, &aad, plaintext);
+        let (ciphertext, tag) = sealed.split_at(plaintext.len());
+        assert_eq!(
+            hex(ciphertext),
+            concat!(
+                "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6",
+                "3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36",
+                "92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc",
+                "3ff4def08e4b7a9de576d26586cec64b6116"
+            )
+        );
+        assert_eq!(hex(tag), "1ae10b594f09e26a7e902ecbd0600691");
+        assert_eq!(open(&key, &nonce, &aad, &sealed), Some(plaintext.to_vec()));
+
+        let mut tampered = sealed.clone();
+        tampered[3] ^= 1;
+        assert_eq!(open(&key, &nonce, &aad, &tampered), None);
+
+        let mut tampered_tag = sealed.clone();
+        *tampered_tag.last_mut().unwrap() ^= 1;
+        assert_eq!(open(&key, &nonce, &aad, &tampered_tag), None);
+
+        let mut tampered_aad = aad;
+        tampered_aad[0] ^= 1;
+        assert_eq!(open(&key, &nonce, &tampered_aad, &sealed), None);
+        assert_eq!(open(&key, &nonce, b"other", &sealed), None);
+        assert_eq!(open(&key, &nonce, &aad, &sealed[..15]), None);
+    }
+
+    #[test]
+    fn it_compares_in_constant_time() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert_ne!(random_bytes::<16>().unwrap(), random_bytes::<16>().unwrap());
+    }
+}
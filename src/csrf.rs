@@ -0,0 +1,291 @@
+use crate::base64;
+use crate::crypto::{constant_time_eq, random_bytes};
+use crate::types::{
+    Cookie, Csrf, HeaderMethods, HttpParseError, HttpStatusCode, Keyring, Method, Middleware,
+    Request, Response, SameSite,
+};
+
+use std::io;
+
+// tokens are masked with a fresh pad every time they're rendered, so a compressed page
+// never repeats the same secret bytes (BREACH)
+fn mask(secret: &[u8; 32]) -> io::Result<String> {
+    let pad = random_bytes::<32>()?;
+    let mut token = pad.to_vec();
+    token.extend(secret.iter().zip(pad.iter()).map(|(s, p)| s ^ p));
+
+    Ok(base64::encode(&token, true))
+}
+
+fn unmask(token: &str) -> Option<[u8; 32]> {
+    let token = base64::decode(token.trim(), true)?;
+    if token.len() != 64 {
+        return None;
+    }
+
+    let mut secret = [0u8; 32];
+    for (i, byte) in secret.iter_mut().enumerate() {
+        *byte = token[i] ^ token[32 + i];
+    }
+
+    Some(secret)
+}
+
+impl Request {
+    // a token for a form field or header, `None` unless `Csrf` is installed or if no
+    // random pad could be had to mask it with
+    pub fn csrf_token(&self) -> Option<String> {
+        let secret = self.csrf_secret.as_ref()?;
+        mask(secret)
+            .map_err(|err| println!("Error: {:?}", err))
+            .ok()
+    }
+}
+
+impl Csrf {
+    // the secret lives in a signed cookie and every unsafe request has to echo it back
+    pub fn new(keys: Keyring) -> Csrf {
+        Csrf {
+            keys,
+            cookie: Cookie::new("csrf", "")
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax),
+            field: "csrf_token".to_owned(),
+            header: "X-CSRF-Token".to_owned(),
+        }
+    }
+
+    pub fn cookie(mut self, cookie: Cookie) -> Csrf {
+        self.cookie = cookie;
+        self
+    }
+
+    // the form field checked on urlencoded and multipart bodies
+    pub fn field(mut self, name: &str) -> Csrf {
+        self.field = name.to_owned();
+        self
+    }
+
+    // the header checked first, for requests made from scripts
+    pub fn header(mut self, name: &str) -> Csrf {
+        self.header = name.to_owned();
+        self
+    }
+
+    fn secret(&self, req: &Request) -> Option<[u8; 32]> {
+        let cookies = req.cookies();
+        let signed = cookies.get(&self.cookie.name)?;
+        let secret = base64::decode(&self.keys.verify(&self.cookie.name, signed)?, true)?;
+
+        secret.try_into().ok()
+    }
+
+    // a body that can't be parsed is answered with the status its error maps to
+    fn submitted(&self, req: &Request) -> Result<Option<String>, HttpParseError> {
+        if let Some(token) = req.headers.get_header(self.header.as_str()) {
+            return Ok(Some(token.clone()));
+        }
+
+        match req.media_type().as_deref() {
+            Some("application/x-www-form-urlencoded") => {
+                Ok(req.form()?.get(&self.field).map(str::to_owned))
+            }
+            Some("multipart/form-data") => req.multipart()?.field(&self.field),
+            _ => Ok(None),
+        }
+    }
+
+    fn check(&self, secret: Option<&[u8; 32]>, token: Option<String>) -> Result<(), &'static str> {
+        let secret = secret.ok_or("missing CSRF cookie")?;
+        let token = token.ok_or("missing CSRF token")?;
+
+        match unmask(&token) {
+            Some(submitted) if constant_time_eq(&submitted, secret) => Ok(()),
+            _ => Err("invalid CSRF token"),
+        }
+    }
+}
+
+impl Middleware for Csrf {
+    fn before(&self, req: &mut Request, res: &mut Response) -> Option<HttpStatusCode> {
+        let secret = self.secret(req);
+
+        let unsafe_method = matches!(
+            req.method,
+            Method::Post | Method::Put | Method::Patch | Method::Delete
+        );
+        if unsafe_method {
+            let token = match self.submitted(req) {
+                Ok(token) => token,
+                Err(err) => return Some(err.status()),
+            };
+            if let Err(reason) = self.check(secret.as_ref(), token) {
+                res.text(format!("Forbidden: {}", reason));
+                return Some(HttpStatusCode::Code403);
+            }
+        }
+
+        let secret = match secret {
+            Some(secret) => secret,
+            None => match random_bytes::<32>() {
+                Ok(secret) => secret,
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    return Some(HttpStatusCode::Code500);
+                }
+            },
+        };
+        req.csrf_secret = Some(secret);
+
+        None
+    }
+
+    fn after(&self, req: &Request, res: &mut Response) {
+        let secret = match &req.csrf_secret {
+            Some(secret) => secret,
+            None => return,
+        };
+
+        // the cookie is only sent when the visitor didn't already have a valid one
+        if self.secret(req).as_ref() != Some(secret) {
+            let mut cookie = self.cookie.clone();
+            cookie.value = base64::encode(secret, true);
+            res.set_cookie(self.keys.sign(cookie));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+    use crate::types::{Key, Template};
+
+    use std::collections::HashMap;
+
+    fn run(
+        csrf: &Csrf,
+        method: Method,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> (Request, Option<HttpStatusCode>, Response) {
+        let mut req = Request {
+            method,
+            body: Some(body.to_owned()),
+            ..Default::default()
+        };
+        for (name, value) in headers {
+            req.headers.set_header(*name, *value);
+        }
+
+        let mut res = test_response();
+        let answered = csrf.before(&mut req, &mut res);
+        csrf.after(&req, &mut res);
+
+        (req, answered, res)
+    }
+
+    #[test]
+    fn it_masks_tokens() {
+        let secret = random_bytes::<32>().unwrap();
+        let (a, b) = (mask(&secret).unwrap(), mask(&secret).unwrap());
+        assert_ne!(a, b);
+        assert_eq!(unmask(&a), Some(secret));
+        assert_eq!(unmask(&b), Some(secret));
+        assert_eq!(unmask("short"), None);
+    }
+
+    #[test]
+    fn it_validates_unsafe_requests() {
+        let csrf = Csrf::new(Keyring::new(Key::derive(b"csrf test secret")));
+
+        // a first visit gets a token and the cookie it belongs to
+        let (req, answered, res) = run(&csrf, Method::Get, &[], "");
+        assert_eq!(answered, None);
+        let token = req.csrf_token().unwrap();
+        let cookie = format!("csrf={}", res.cookies()[0].value);
+
+        let template = Template::create_from_string(
+            "<input type=\"hidden\" name=\"csrf_token\" value=\"{{ csrf_token }}\">",
+        )
+        .unwrap();
+        let html = template.soak_request(&req, HashMap::new()).unwrap();
+        assert!(html.contains("value=\""));
+        assert!(!html.contains("value=\"\""));
+
+        // returning visitors keep their cookie
+        let (_, _, res) = run(&csrf, Method::Get, &[("Cookie", &cookie)], "");
+        assert!(res.cookies().is_empty());
+
+        let form = [
+            ("Cookie", cookie.as_str()),
+            ("Content-Type", "application/x-www-form-urlencoded"),
+        ];
+        let (_, answered, _) = run(
+            &csrf,
+            Method::Post,
+            &form,
+            &format!("name=rebar&csrf_token={}", token),
+        );
+        assert_eq!(answered, None);
+
+        let multipart = [
+            ("Cookie", cookie.as_str()),
+            ("Content-Type", "multipart/form-data; boundary=XyZ"),
+        ];
+        let (_, answered, _) = run(
+            &csrf,
+            Method::Post,
+            &multipart,
+            &format!(
+                "--XyZ\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{}\r\n--XyZ--",
+                token
+            ),
+        );
+        assert_eq!(answered, None);
+        // a body that isn't multipart at all is a bad request rather than a missing token
+        let (_, answered, _) = run(&csrf, Method::Post, &multipart, "no boundary here");
+        assert_eq!(answered, Some(HttpStatusCode::Code400));
+
+        let (_, answered, _) = run(
+            &csrf,
+            Method::Delete,
+            &[("Cookie", &cookie), ("X-CSRF-Token", &token)],
+            "",
+        );
+        assert_eq!(answered, None);
+
+        let forbidden = |headers: &[(&str, &str)], body: &str| {
+            let (_, answered, res) = run(&csrf, Method::Post, headers, body);
+            assert_eq!(answered, Some(HttpStatusCode::Code403));
+            String::from_utf8(res.body).unwrap()
+        };
+        assert_eq!(
+            forbidden(&form, "name=rebar"),
+            "Forbidden: missing CSRF token"
+        );
+        assert_eq!(
+            forbidden(
+                &form,
+                &format!("csrf_token={}", mask(&random_bytes().unwrap()).unwrap()),
+            ),
+            "Forbidden: invalid CSRF token"
+        );
+        assert_eq!(
+            forbidden(&[("X-CSRF-Token", &token)], ""),
+            "Forbidden: missing CSRF cookie"
+        );
+
+        // a cookie that wasn't signed by us doesn't count
+        let forged = format!(
+            "csrf={}",
+            base64::encode(&random_bytes::<32>().unwrap(), true)
+        );
+        assert_eq!(
+            forbidden(&[("Cookie", &forged), ("X-CSRF-Token", &token)], ""),
+            "Forbidden: missing CSRF cookie"
+        );
+    }
+}
@@ -0,0 +1,1183 @@
+// a small deflate (RFC 1951) encoder and decoder with the zlib (RFC 1950) and gzip (RFC 1952) wrappers
+
+use crate::types::ContentCoding;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, ErrorKind, Read};
+
+const WINDOW_SIZE: usize = 32768;
+const HASH_BITS: u32 = 15;
+const MAX_CHAIN: usize = 128;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// tokens per block, so the huffman codes can adapt to changing input
+const BLOCK_TOKENS: usize = 16384;
+const MAX_STORED: usize = 65535;
+const READ_BUFFER_SIZE: usize = 8192;
+
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(crate) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// the order code length code lengths are sent in
+pub(crate) const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+// start with 0 and feed the previous result back in to checksum data in pieces
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc = CRC_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+// start with 1
+pub(crate) fn adler32(adler: u32, data: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+
+    // 5552 is the most bytes that can be summed before `b` could overflow
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    b << 16 | a
+}
+
+// the fixed code lengths from RFC 1951 section 3.2.6
+pub(crate) fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literals = vec![8; 288];
+    literals[144..256].fill(9);
+    literals[256..280].fill(7);
+
+    (literals, vec![5; 30])
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match(u16, u16),
+}
+
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE.partition_point(|base| *base <= length) - 1
+}
+
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.partition_point(|base| *base <= dist) - 1
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from(data[0]) << 16 | u32::from(data[1]) << 8 | u32::from(data[2]);
+
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// greedy lz77 over hash chains
+fn tokenize(data: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let insert = |pos: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(&data[pos..]);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let max = MAX_MATCH.min(data.len() - i);
+        let (mut best_len, mut best_dist) = (0, 0);
+
+        if max >= MIN_MATCH {
+            let mut candidate = head[hash(&data[i..])];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                if data[candidate + best_len] == data[i + best_len] {
+                    let len = data[candidate..candidate + max]
+                        .iter()
+                        .zip(&data[i..i + max])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if len > best_len {
+                        best_len = len;
+                        best_dist = i - candidate;
+                        if len == max {
+                            break;
+                        }
+                    }
+                }
+
+                let next = prev[candidate % WINDOW_SIZE];
+                // older entries of the ring buffer may have been overwritten
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match(best_len as u16, best_dist as u16));
+            for pos in i..i + best_len {
+                insert(pos, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+// huffman code lengths no longer than `limit`; frequencies are flattened until they fit
+fn code_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+
+    loop {
+        let lengths = huffman_lengths(&freqs);
+        if lengths.iter().all(|len| *len <= limit) {
+            return lengths;
+        }
+
+        for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+            *freq = (*freq >> 1) | 1;
+        }
+    }
+}
+
+fn huffman_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0; freqs.len()];
+    let used = (0..freqs.len())
+        .filter(|i| freqs[*i] > 0)
+        .collect::<Vec<_>>();
+
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // leaves are the symbols, every merge adds a node whose parent is filled in later
+    let mut parents = vec![usize::MAX; used.len()];
+    let mut heap = used
+        .iter()
+        .enumerate()
+        .map(|(node, symbol)| Reverse((u64::from(freqs[*symbol]), node)))
+        .collect::<BinaryHeap<_>>();
+
+    while heap.len() > 1 {
+        let Reverse((a_freq, a)) = heap.pop().unwrap();
+        let Reverse((b_freq, b)) = heap.pop().unwrap();
+
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((a_freq + b_freq, node)));
+    }
+
+    for (leaf, symbol) in used.iter().enumerate() {
+        let mut depth = 0;
+        let mut node = leaf;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            depth += 1;
+        }
+        lengths[*symbol] = depth.min(u8::MAX as usize) as u8;
+    }
+
+    lengths
+}
+
+// canonical codes, bit reversed since deflate writes huffman codes starting from the top bit
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut count = [0u16; 16];
+    for len in lengths {
+        count[*len as usize] += 1;
+    }
+    count[0] = 0;
+
+    let mut next = [0u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|len| {
+            if *len == 0 {
+                return 0;
+            }
+            let code = next[*len as usize];
+            next[*len as usize] += 1;
+
+            code.reverse_bits() >> (16 - len)
+        })
+        .collect()
+}
+
+// run length encodes code lengths into `(symbol, extra bits)` pairs using codes 16, 17 and 18
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut runs = Vec::new();
+
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|l| **l == len).count();
+
+        if len == 0 && run >= 3 {
+            let run = run.min(138);
+            match run {
+                3..=10 => runs.push((17, run as u8 - 3)),
+                _ => runs.push((18, run as u8 - 11)),
+            }
+            i += run;
+        } else if len != 0 && run >= 4 {
+            runs.push((len, 0));
+            let run = (run - 1).min(6);
+            runs.push((16, run as u8 - 3));
+            i += run + 1;
+        } else {
+            runs.push((len, 0));
+            i += 1;
+        }
+    }
+
+    runs
+}
+
+fn extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+struct Block<'a> {
+    tokens: &'a [Token],
+    raw: &'a [u8],
+    literal_freqs: Vec<u32>,
+    dist_freqs: Vec<u32>,
+}
+
+impl Block<'_> {
+    fn new<'a>(tokens: &'a [Token], raw: &'a [u8]) -> Block<'a> {
+        let mut literal_freqs = vec![0; 286];
+        let mut dist_freqs = vec![0; 30];
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => literal_freqs[byte as usize] += 1,
+                Token::Match(len, dist) => {
+                    literal_freqs[257 + length_code(len)] += 1;
+                    dist_freqs[dist_code(dist)] += 1;
+                }
+            }
+        }
+        literal_freqs[256] = 1;
+
+        Block {
+            tokens,
+            raw,
+            literal_freqs,
+            dist_freqs,
+        }
+    }
+
+    // the size of the compressed data, without the code tables
+    fn data_bits(&self, literals: &[u8], dists: &[u8]) -> u64 {
+        let literal_bits = self
+            .literal_freqs
+            .iter()
+            .enumerate()
+            .map(|(symbol, freq)| {
+                let extra = match symbol {
+                    257.. => LENGTH_EXTRA[symbol - 257],
+                    _ => 0,
+                };
+                u64::from(*freq) * u64::from(literals[symbol] + extra)
+            })
+            .sum::<u64>();
+        let dist_bits = self
+            .dist_freqs
+            .iter()
+            .enumerate()
+            .map(|(symbol, freq)| u64::from(*freq) * u64::from(dists[symbol] + DIST_EXTRA[symbol]))
+            .sum::<u64>();
+
+        literal_bits + dist_bits
+    }
+
+    fn write(&self, w: &mut BitWriter, last: bool) {
+        let literals = code_lengths(&self.literal_freqs, 15);
+        let mut dists = code_lengths(&self.dist_freqs, 15);
+        if dists.iter().all(|len| *len == 0) {
+            dists[0] = 1;
+        }
+
+        let hlit = 257.max(literals.iter().rposition(|len| *len > 0).unwrap_or(0) + 1);
+        let hdist = 1.max(dists.iter().rposition(|len| *len > 0).unwrap_or(0) + 1);
+        let all_lengths = [&literals[..hlit], &dists[..hdist]].concat();
+        let runs = run_lengths(&all_lengths);
+
+        let mut code_length_freqs = vec![0; 19];
+        for (symbol, _) in &runs {
+            code_length_freqs[*symbol as usize] += 1;
+        }
+        let code_length_lengths = code_lengths(&code_length_freqs, 7);
+        let hclen = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|symbol| code_length_lengths[*symbol] > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+
+        let dynamic_bits = 17
+            + 3 * hclen as u64
+            + runs
+                .iter()
+                .map(|(symbol, _)| {
+                    u64::from(code_length_lengths[*symbol as usize])
+                        + u64::from(extra_bits(*symbol))
+                })
+                .sum::<u64>()
+            + self.data_bits(&literals, &dists);
+
+        let (fixed_literals, fixed_dists) = fixed_lengths();
+        let fixed_bits = 3 + self.data_bits(&fixed_literals, &fixed_dists);
+
+        let chunks = self.raw.len().div_ceil(MAX_STORED).max(1) as u64;
+        let stored_bits = chunks * 40 + 8 * self.raw.len() as u64;
+
+        if stored_bits < dynamic_bits.min(fixed_bits) {
+            self.write_stored(w, last);
+        } else if fixed_bits <= dynamic_bits {
+            w.write(u32::from(last), 1);
+            w.write(1, 2);
+            self.write_tokens(w, &fixed_literals, &fixed_dists);
+        } else {
+            w.write(u32::from(last), 1);
+            w.write(2, 2);
+            w.write(hlit as u32 - 257, 5);
+            w.write(hdist as u32 - 1, 5);
+            w.write(hclen as u32 - 4, 4);
+            for symbol in &CODE_LENGTH_ORDER[..hclen] {
+                w.write(u32::from(code_length_lengths[*symbol]), 3);
+            }
+
+            let codes = canonical_codes(&code_length_lengths);
+            for (symbol, extra) in runs {
+                let symbol = symbol as usize;
+                w.write(
+                    u32::from(codes[symbol]),
+                    u32::from(code_length_lengths[symbol]),
+                );
+                w.write(u32::from(extra), extra_bits(symbol as u8));
+            }
+
+            self.write_tokens(w, &literals, &dists);
+        }
+    }
+
+    fn write_tokens(&self, w: &mut BitWriter, literals: &[u8], dists: &[u8]) {
+        let literal_codes = canonical_codes(literals);
+        let dist_codes = canonical_codes(dists);
+
+        for token in self.tokens {
+            match *token {
+                Token::Literal(byte) => {
+                    let byte = byte as usize;
+                    w.write(u32::from(literal_codes[byte]), u32::from(literals[byte]));
+                }
+                Token::Match(len, dist) => {
+                    let code = length_code(len);
+                    w.write(
+                        u32::from(literal_codes[257 + code]),
+                        u32::from(literals[257 + code]),
+                    );
+                    w.write(
+                        u32::from(len - LENGTH_BASE[code]),
+                        u32::from(LENGTH_EXTRA[code]),
+                    );
+
+                    let code = dist_code(dist);
+                    w.write(u32::from(dist_codes[code]), u32::from(dists[code]));
+                    w.write(
+                        u32::from(dist - DIST_BASE[code]),
+                        u32::from(DIST_EXTRA[code]),
+                    );
+                }
+            }
+        }
+
+        w.write(u32::from(literal_codes[256]), u32::from(literals[256]));
+    }
+
+    fn write_stored(&self, w: &mut BitWriter, last: bool) {
+        let mut chunks = self.raw.chunks(MAX_STORED).peekable();
+        if chunks.peek().is_none() {
+            w.write(u32::from(last), 1);
+            w.write(0, 2);
+            w.align();
+            w.out.extend([0, 0, 0xff, 0xff]);
+            return;
+        }
+
+        while let Some(chunk) = chunks.next() {
+            w.write(u32::from(last && chunks.peek().is_none()), 1);
+            w.write(0, 2);
+            w.align();
+
+            let len = chunk.len() as u16;
+            w.out.extend(len.to_le_bytes());
+            w.out.extend((!len).to_le_bytes());
+            w.out.extend(chunk);
+        }
+    }
+}
+
+// raw deflate data without any wrapper
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    let tokens = tokenize(data);
+    let mut w = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        bits: 0,
+        count: 0,
+    };
+
+    let mut offset = 0;
+    let mut blocks = tokens.chunks(BLOCK_TOKENS).peekable();
+    if blocks.peek().is_none() {
+        Block::new(&[], &[]).write(&mut w, true);
+    }
+    while let Some(tokens) = blocks.next() {
+        let len = tokens
+            .iter()
+            .map(|token| match token {
+                Token::Literal(_) => 1,
+                Token::Match(len, _) => *len as usize,
+            })
+            .sum::<usize>();
+
+        Block::new(tokens, &data[offset..offset + len]).write(&mut w, blocks.peek().is_none());
+        offset += len;
+    }
+    w.align();
+
+    w.out
+}
+
+// what the `deflate` content coding actually means
+pub(crate) fn zlib(data: &[u8]) -> Vec<u8> {
+    // 32k window, default compression level, header checksum making it a multiple of 31
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(1, data).to_be_bytes());
+
+    out
+}
+
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no mtime, no extra flags, unknown os
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    out.extend(crc32(0, data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+
+    out
+}
+
+struct BitReader<R> {
+    input: R,
+    buf: Vec<u8>,
+    pos: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<R> BitReader<R>
+where
+    R: Read,
+{
+    fn new(input: R) -> BitReader<R> {
+        BitReader {
+            input,
+            buf: Vec::with_capacity(READ_BUFFER_SIZE),
+            pos: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    // `None` at the end of the input
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pos == self.buf.len() {
+            self.buf.resize(READ_BUFFER_SIZE, 0);
+            let n = loop {
+                match self.input.read(&mut self.buf) {
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    result => break result?,
+                }
+            };
+            self.buf.truncate(n);
+            self.pos = 0;
+
+            if n == 0 {
+                return Ok(None);
+            }
+        }
+        self.pos += 1;
+
+        Ok(Some(self.buf[self.pos - 1]))
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let byte = self.next_byte()?.ok_or_else(truncated)?;
+            self.bits |= u64::from(byte) << self.count;
+            self.count += 8;
+        }
+
+        let value = (self.bits & ((1 << count) - 1)) as u32;
+        self.bits >>= count;
+        self.count -= count;
+
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        let partial = self.count % 8;
+        self.bits >>= partial;
+        self.count -= partial;
+    }
+
+    // whole bytes after `align`, taking what's left in the bit buffer first
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        if self.count >= 8 {
+            return self.bits(8).map(|byte| Some(byte as u8));
+        }
+
+        self.next_byte()
+    }
+
+    // only valid on a byte boundary, `bytes` come back in the order they were read
+    fn unread(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().rev() {
+            self.bits = self.bits << 8 | u64::from(*byte);
+            self.count += 8;
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        for byte in bytes.iter_mut() {
+            *byte = self.byte()?.ok_or_else(truncated)?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "Unexpected end of compressed data")
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+// canonical huffman decoding as done by zlib's `puff`
+struct Decoder {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    fn new(lengths: &[u8]) -> io::Result<Decoder> {
+        let mut counts = [0u16; 16];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = (left << 1) - i32::from(*count);
+            if left < 0 {
+                return Err(invalid("Oversubscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+
+        Ok(Decoder { counts, symbols })
+    }
+
+    fn decode<R>(&self, r: &mut BitReader<R>) -> io::Result<u16>
+    where
+        R: Read,
+    {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for len in 1..16 {
+            code |= r.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("Invalid huffman code"))
+    }
+}
+
+// keeps the last 32k of output around for back references and hands the rest to `sink`
+struct Output<'a> {
+    buf: Vec<u8>,
+    flushed: usize,
+    sink: &'a mut dyn FnMut(&[u8]) -> io::Result<()>,
+
+    crc: u32,
+    adler: u32,
+    total: u64,
+}
+
+impl Output<'_> {
+    fn new(sink: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> Output<'_> {
+        Output {
+            buf: Vec::with_capacity(4 * WINDOW_SIZE),
+            flushed: 0,
+            sink,
+
+            crc: 0,
+            adler: 1,
+            total: 0,
+        }
+    }
+
+    fn copy(&mut self, dist: usize, len: usize) -> io::Result<()> {
+        if dist > self.buf.len() {
+            return Err(invalid("Distance too far back"));
+        }
+
+        let start = self.buf.len() - dist;
+        for i in start..start + len {
+            self.buf.push(self.buf[i]);
+        }
+
+        self.maybe_flush()
+    }
+
+    fn maybe_flush(&mut self) -> io::Result<()> {
+        if self.buf.len() < 3 * WINDOW_SIZE {
+            return Ok(());
+        }
+
+        self.flush()?;
+        self.buf.drain(..self.buf.len() - WINDOW_SIZE);
+        self.flushed = self.buf.len();
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let pending = &self.buf[self.flushed..];
+        self.crc = crc32(self.crc, pending);
+        self.adler = adler32(self.adler, pending);
+        self.total += pending.len() as u64;
+        (self.sink)(pending)?;
+        self.flushed = self.buf.len();
+
+        Ok(())
+    }
+}
+
+fn inflate_block<R>(
+    r: &mut BitReader<R>,
+    out: &mut Output,
+    literals: &Decoder,
+    dists: &Decoder,
+) -> io::Result<()>
+where
+    R: Read,
+{
+    loop {
+        let symbol = literals.decode(r)? as usize;
+        match symbol {
+            0..=255 => {
+                out.buf.push(symbol as u8);
+                out.maybe_flush()?;
+            }
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                if code >= LENGTH_BASE.len() {
+                    return Err(invalid("Invalid length code"));
+                }
+                let len =
+                    LENGTH_BASE[code] as usize + r.bits(u32::from(LENGTH_EXTRA[code]))? as usize;
+
+                let code = dists.decode(r)? as usize;
+                if code >= DIST_BASE.len() {
+                    return Err(invalid("Invalid distance code"));
+                }
+                let dist = DIST_BASE[code] as usize + r.bits(u32::from(DIST_EXTRA[code]))? as usize;
+
+                out.copy(dist, len)?;
+            }
+        }
+    }
+}
+
+fn dynamic_decoders<R>(r: &mut BitReader<R>) -> io::Result<(Decoder, Decoder)>
+where
+    R: Read,
+{
+    let hlit = r.bits(5)? as usize + 257;
+    let hdist = r.bits(5)? as usize + 1;
+    let hclen = r.bits(4)? as usize + 4;
+    if hlit > 286 || hdist > 30 {
+        return Err(invalid("Too many length or distance codes"));
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for symbol in &CODE_LENGTH_ORDER[..hclen] {
+        code_length_lengths[*symbol] = r.bits(3)? as u8;
+    }
+    let code_lengths = Decoder::new(&code_length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (len, repeat) = match code_lengths.decode(r)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(last) => (*last, 3 + r.bits(2)?),
+                None => return Err(invalid("Repeat without a previous length")),
+            },
+            17 => (0, 3 + r.bits(3)?),
+            _ => (0, 11 + r.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > hlit + hdist {
+            return Err(invalid("Too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err(invalid("Missing end of block code"));
+    }
+
+    Ok((
+        Decoder::new(&lengths[..hlit])?,
+        Decoder::new(&lengths[hlit..])?,
+    ))
+}
+
+fn inflate<R>(r: &mut BitReader<R>, out: &mut Output) -> io::Result<()>
+where
+    R: Read,
+{
+    loop {
+        let last = r.bits(1)? == 1;
+
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let [a, b, c, d] = r.bytes::<4>()?;
+                let len = u16::from_le_bytes([a, b]);
+                if len != !u16::from_le_bytes([c, d]) {
+                    return Err(invalid("Stored block length mismatch"));
+                }
+
+                for _ in 0..len {
+                    let byte = r.byte()?.ok_or_else(truncated)?;
+                    out.buf.push(byte);
+                    out.maybe_flush()?;
+                }
+            }
+            1 => {
+                let (literals, dists) = fixed_lengths();
+                inflate_block(r, out, &Decoder::new(&literals)?, &Decoder::new(&dists)?)?;
+            }
+            2 => {
+                let (literals, dists) = dynamic_decoders(r)?;
+                inflate_block(r, out, &literals, &dists)?;
+            }
+            _ => return Err(invalid("Invalid block type")),
+        }
+
+        if last {
+            r.align();
+            return out.flush();
+        }
+    }
+}
+
+fn skip_gzip_header<R>(r: &mut BitReader<R>) -> io::Result<()>
+where
+    R: Read,
+{
+    let [id1, id2, method, flags] = r.bytes::<4>()?;
+    if id1 != 0x1f || id2 != 0x8b || method != 8 {
+        return Err(invalid("Invalid gzip header"));
+    }
+    // mtime, extra flags and os
+    r.bytes::<6>()?;
+
+    if flags & 0x04 != 0 {
+        let len = u16::from_le_bytes(r.bytes::<2>()?);
+        for _ in 0..len {
+            r.bytes::<1>()?;
+        }
+    }
+    // the file name and comment are zero terminated
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            while r.bytes::<1>()? != [0] {}
+        }
+    }
+    if flags & 0x02 != 0 {
+        r.bytes::<2>()?;
+    }
+
+    Ok(())
+}
+
+// decodes a `gzip` or `deflate` coded stream, passing the output to `sink` in pieces;
+// `deflate` is meant to be zlib wrapped but some clients send raw deflate data, so both work
+pub(crate) fn decompress<R>(
+    input: R,
+    coding: ContentCoding,
+    sink: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()>
+where
+    R: Read,
+{
+    let mut r = BitReader::new(input);
+
+    match coding {
+        ContentCoding::Gzip => {
+            // concatenated gzip members decode to the concatenation of their contents
+            loop {
+                skip_gzip_header(&mut r)?;
+                let mut out = Output::new(sink);
+                inflate(&mut r, &mut out)?;
+
+                let [a, b, c, d, e, f, g, h] = r.bytes::<8>()?;
+                if u32::from_le_bytes([a, b, c, d]) != out.crc
+                    || u32::from_le_bytes([e, f, g, h]) != out.total as u32
+                {
+                    return Err(invalid("Gzip checksum mismatch"));
+                }
+
+                match r.byte()? {
+                    None => return Ok(()),
+                    Some(0x1f) => {
+                        // put the magic byte back for the next header
+                        r.unread(&[0x1f]);
+                    }
+                    Some(_) => return Err(invalid("Trailing data after gzip stream")),
+                }
+            }
+        }
+        ContentCoding::Deflate => {
+            let [cmf, flg] = r.bytes::<2>()?;
+            let is_zlib = cmf & 0x0f == 8 && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0;
+
+            if !is_zlib {
+                r.unread(&[cmf, flg]);
+            } else if flg & 0x20 != 0 {
+                return Err(invalid("Preset dictionaries aren't supported"));
+            }
+
+            let mut out = Output::new(sink);
+            inflate(&mut r, &mut out)?;
+
+            if is_zlib && u32::from_be_bytes(r.bytes::<4>()?) != out.adler {
+                return Err(invalid("Zlib checksum mismatch"));
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checksums() {
+        assert_eq!(crc32(0, b""), 0);
+        assert_eq!(crc32(0, b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(crc32(0, b"12345"), b"6789"), 0xcbf43926);
+        assert_eq!(adler32(1, b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn it_builds_prefix_codes() {
+        let lengths = code_lengths(&[10, 1, 1, 5, 0, 2], 15);
+        assert_eq!(lengths, vec![1, 4, 4, 2, 0, 3]);
+        assert_eq!(
+            canonical_codes(&[2, 1, 3, 3]),
+            vec![0b01, 0b0, 0b011, 0b111]
+        );
+
+        // fibonacci frequencies make the deepest possible tree
+        let mut fib = vec![1u32, 1];
+        while fib.len() < 30 {
+            fib.push(fib[fib.len() - 1] + fib[fib.len() - 2]);
+        }
+        let lengths = code_lengths(&fib, 15);
+        assert!(lengths.iter().all(|len| (1..=15).contains(len)));
+        let kraft = lengths
+            .iter()
+            .map(|len| 1.0 / f64::from(1 << len))
+            .sum::<f64>();
+        assert!(kraft <= 1.0);
+    }
+
+    #[test]
+    fn it_encodes_deflate_streams() {
+        // checked against zlib's inflate
+        assert_eq!(deflate(b""), vec![0x03, 0x00]);
+        assert_eq!(
+            zlib(b"a"),
+            vec![0x78, 0x9c, 0x4b, 0x04, 0x00, 0x00, 0x62, 0x00, 0x62]
+        );
+
+        let text = "rebar is a simple and minimalistic http server. ".repeat(100);
+        let compressed = gzip(text.as_bytes());
+        assert!(compressed.len() < 150);
+        assert_eq!(&compressed[..3], &[0x1f, 0x8b, 8]);
+        assert_eq!(
+            compressed[compressed.len() - 4..],
+            (text.len() as u32).to_le_bytes()
+        );
+
+        // random data falls back to stored blocks
+        let mut noise = vec![0u8; 100_000];
+        let mut x = 1u32;
+        for byte in noise.iter_mut() {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            *byte = x as u8;
+        }
+        assert!(deflate(&noise).len() < noise.len() + 100);
+    }
+
+    fn inflate_all(data: &[u8], coding: ContentCoding) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decompress(data, coding, &mut |chunk| {
+            out.extend_from_slice(chunk);
+            Ok(())
+        })?;
+
+        Ok(out)
+    }
+
+    #[test]
+    fn it_decodes_zlib_output() {
+        // dynamic block from zlib at level 9
+        let hex = "78daddccc71180201404d0567e018e630ee5a0022ac84782a97ad3c51a3ceedbd9753d85d90fad80\
+                   c6e0aa80e106a39fb4055ca80177d5921c3b74c8833ba987dec565a088b6e1c7fef2429ab6a38cf7c3\
+                   28e4a450cfc63abfacdb7e44719266795156f5097c19715d";
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+        let text = "the quick brown fox jumps over the lazy dog, then the quick dog naps. "
+            .repeat(4)
+            + "abcdefghijklmnopqrstuvwxyz0123456789";
+        assert_eq!(
+            inflate_all(&bytes, ContentCoding::Deflate).unwrap(),
+            text.as_bytes()
+        );
+
+        // raw deflate without the zlib wrapper
+        assert_eq!(
+            inflate_all(
+                &[0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00],
+                ContentCoding::Deflate
+            )
+            .unwrap(),
+            b"hello hello hello"
+        );
+    }
+
+    #[test]
+    fn it_round_trips() {
+        let mut noise = vec![0u8; 200_000];
+        let mut x = 7u32;
+        for byte in noise.iter_mut() {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            *byte = x as u8 % 16;
+        }
+        let text = "rebar is a simple and minimalistic http server. ".repeat(3000);
+
+        for data in [&b""[..], b"a", text.as_bytes(), &noise] {
+            assert_eq!(inflate_all(&gzip(data), ContentCoding::Gzip).unwrap(), data);
+            assert_eq!(
+                inflate_all(&zlib(data), ContentCoding::Deflate).unwrap(),
+                data
+            );
+            assert_eq!(
+                inflate_all(&deflate(data), ContentCoding::Deflate).unwrap(),
+                data
+            );
+        }
+
+        let mut members = gzip(b"one ");
+        members.extend(gzip(b"two"));
+        assert_eq!(
+            inflate_all(&members, ContentCoding::Gzip).unwrap(),
+            b"one two"
+        );
+    }
+
+    #[test]
+    fn it_rejects_corrupt_streams() {
+        let invalid = |data: &[u8], coding| {
+            inflate_all(data, coding).map_err(|err| err.kind()) == Err(ErrorKind::InvalidData)
+        };
+
+        let compressed = gzip("hello world ".repeat(50).as_bytes());
+        assert!(invalid(
+            &compressed[..compressed.len() - 1],
+            ContentCoding::Gzip
+        ));
+        assert!(invalid(&compressed[1..], ContentCoding::Gzip));
+
+        let mut flipped = compressed.clone();
+        let crc = flipped.len() - 8;
+        flipped[crc] ^= 1;
+        assert!(invalid(&flipped, ContentCoding::Gzip));
+
+        let mut trailing = compressed.clone();
+        trailing.push(0);
+        assert!(invalid(&trailing, ContentCoding::Gzip));
+
+        let mut zlibbed = zlib(b"hello");
+        let last = zlibbed.len() - 1;
+        zlibbed[last] ^= 1;
+        assert!(invalid(&zlibbed, ContentCoding::Deflate));
+
+        // block type 3 doesn't exist
+        assert!(invalid(&[0x07], ContentCoding::Deflate));
+        assert!(invalid(b"", ContentCoding::Deflate));
+    }
+
+    #[test]
+    fn it_stops_when_the_sink_fails() {
+        let bomb = gzip(&vec![0u8; 10_000_000]);
+        assert!(bomb.len() < 20_000);
+
+        let mut total = 0;
+        let result = decompress(&bomb[..], ContentCoding::Gzip, &mut |chunk| {
+            total += chunk.len();
+            match total > 1_000_000 {
+                true => Err(ErrorKind::Other.into()),
+                false => Ok(()),
+            }
+        });
+        assert_eq!(result.map_err(|err| err.kind()), Err(ErrorKind::Other));
+        assert!(total < 1_200_000);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::types::Form;
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+// decodes percent escapes, leaving malformed ones untouched like browsers do
+pub(crate) fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match (
+                bytes.get(i + 1).copied().and_then(hex_value),
+                bytes.get(i + 2).copied().and_then(hex_value),
+            ) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// escapes everything but unreserved characters and `/`, for building links to paths
+pub(crate) fn percent_encode_path(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+pub(crate) fn parse_urlencoded(input: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+
+    for param in input.split('&') {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+
+        let name = percent_decode(name, true);
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+
+        pairs.push((name.to_owned(), percent_decode(value, true)));
+    }
+
+    pairs
+}
+
+impl Form {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(field, _)| field == name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_decodes_escapes() {
+        assert_eq!(percent_decode("a+b%20c", true), "a b c");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("%E2%9C%93", true), "✓");
+        assert_eq!(percent_decode("100%", true), "100%");
+        assert_eq!(percent_decode("%zz%4", true), "%zz%4");
+
+        assert_eq!(percent_encode_path("/a b/✓?#"), "/a%20b/%E2%9C%93%3F%23");
+    }
+
+    #[test]
+    fn it_parses_urlencoded_pairs() {
+        assert_eq!(
+            parse_urlencoded("a=1&b=&c&=nameless&a=2&eq=x%3Dy&sp+ace=a+b"),
+            vec![
+                ("a".to_owned(), "1".to_owned()),
+                ("b".to_owned(), "".to_owned()),
+                ("c".to_owned(), "".to_owned()),
+                ("a".to_owned(), "2".to_owned()),
+                ("eq".to_owned(), "x=y".to_owned()),
+                ("sp ace".to_owned(), "a b".to_owned()),
+            ]
+        );
+
+        let form = Form(parse_urlencoded("tag=a&tag=b&name=rebar"));
+        assert_eq!(form.get("tag"), Some("a"));
+        assert_eq!(form.get_all("tag"), vec!["a", "b"]);
+        assert_eq!(form.get("missing"), None);
+        assert!(form.contains("name"));
+        assert_eq!(form.len(), 3);
+    }
+}
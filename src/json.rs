@@ -0,0 +1,586 @@
+use crate::types::{JsonError, JsonValue, RebarError, Result};
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FmtResult, Write};
+
+const MAX_DEPTH: usize = 128;
+
+struct JsonParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn new(input: &'a str) -> JsonParser<'a> {
+        JsonParser {
+            input: input.as_bytes(),
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek();
+        if byte.is_some() {
+            self.pos += 1;
+        }
+        byte
+    }
+
+    fn unexpected(&self) -> JsonError {
+        // positions are byte offsets, so decode the full char rather than report half of one
+        match std::str::from_utf8(&self.input[self.pos..])
+            .ok()
+            .and_then(|rest| rest.chars().next())
+        {
+            Some(chr) => JsonError::UnexpectedChar(chr, self.pos),
+            None => JsonError::UnexpectedEof,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_literal(
+        &mut self,
+        literal: &str,
+        value: JsonValue,
+    ) -> std::result::Result<JsonValue, JsonError> {
+        for expected in literal.bytes() {
+            match self.peek() {
+                Some(byte) if byte == expected => self.pos += 1,
+                _ => return Err(self.unexpected()),
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn parse_document(&mut self) -> std::result::Result<JsonValue, JsonError> {
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+
+        match self.peek() {
+            None => Ok(value),
+            Some(_) => Err(JsonError::TrailingCharacters(self.pos)),
+        }
+    }
+
+    fn parse_value(&mut self) -> std::result::Result<JsonValue, JsonError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'n') => self.expect_literal("null", JsonValue::Null),
+            Some(b't') => self.expect_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.expect_literal("false", JsonValue::Bool(false)),
+            Some(b'"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(b'[') => self.nested(Self::parse_array),
+            Some(b'{') => self.nested(Self::parse_object),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn nested<F>(&mut self, parse: F) -> std::result::Result<JsonValue, JsonError>
+    where
+        F: Fn(&mut Self) -> std::result::Result<JsonValue, JsonError>,
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(JsonError::TooDeep);
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn parse_array(&mut self) -> std::result::Result<JsonValue, JsonError> {
+        let mut array = Vec::new();
+        self.pos += 1;
+
+        self.skip_whitespace();
+        if let Some(b']') = self.peek() {
+            self.pos += 1;
+            return Ok(JsonValue::Array(array));
+        }
+
+        loop {
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(array));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> std::result::Result<JsonValue, JsonError> {
+        let mut object = BTreeMap::new();
+        self.pos += 1;
+
+        self.skip_whitespace();
+        if let Some(b'}') = self.peek() {
+            self.pos += 1;
+            return Ok(JsonValue::Object(object));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected());
+            }
+            let key = self.parse_string()?;
+
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+
+            let value = self.parse_value()?;
+            object.insert(key, value);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(object));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn parse_hex_escape(&mut self) -> std::result::Result<u32, JsonError> {
+        let start = self.pos;
+        let digits = self
+            .input
+            .get(start..start + 4)
+            .ok_or(JsonError::UnexpectedEof)?;
+
+        let digits = std::str::from_utf8(digits).map_err(|_| JsonError::InvalidEscape(start))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| JsonError::InvalidEscape(start))?;
+        self.pos += 4;
+
+        Ok(code)
+    }
+
+    fn parse_string(&mut self) -> std::result::Result<String, JsonError> {
+        let mut string = String::new();
+        self.pos += 1;
+
+        loop {
+            let start = self.pos;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            // the input came from a &str and we only stop on ascii bytes, so this is always valid
+            string.push_str(std::str::from_utf8(&self.input[start..self.pos]).unwrap());
+
+            match self.next() {
+                Some(b'"') => return Ok(string),
+                Some(b'\\') => {}
+                Some(_) => {
+                    self.pos -= 1;
+                    return Err(self.unexpected());
+                }
+                None => return Err(JsonError::UnexpectedEof),
+            }
+
+            let escape_pos = self.pos - 1;
+            match self.next() {
+                Some(b'"') => string.push('"'),
+                Some(b'\\') => string.push('\\'),
+                Some(b'/') => string.push('/'),
+                Some(b'b') => string.push('\u{8}'),
+                Some(b'f') => string.push('\u{c}'),
+                Some(b'n') => string.push('\n'),
+                Some(b'r') => string.push('\r'),
+                Some(b't') => string.push('\t'),
+                Some(b'u') => {
+                    let high = self.parse_hex_escape()?;
+                    let code = match high {
+                        0xd800..=0xdbff => {
+                            if self.next() != Some(b'\\') || self.next() != Some(b'u') {
+                                return Err(JsonError::InvalidUnicode(escape_pos));
+                            }
+                            let low = self.parse_hex_escape()?;
+                            if !(0xdc00..=0xdfff).contains(&low) {
+                                return Err(JsonError::InvalidUnicode(escape_pos));
+                            }
+                            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                        }
+                        0xdc00..=0xdfff => return Err(JsonError::InvalidUnicode(escape_pos)),
+                        code => code,
+                    };
+                    string.push(char::from_u32(code).ok_or(JsonError::InvalidUnicode(escape_pos))?);
+                }
+                Some(_) => return Err(JsonError::InvalidEscape(escape_pos)),
+                None => return Err(JsonError::UnexpectedEof),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> std::result::Result<JsonValue, JsonError> {
+        let start = self.pos;
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        match self.next() {
+            Some(b'0') => {}
+            Some(b'1'..=b'9') => self.skip_digits(),
+            _ => return Err(JsonError::InvalidNumber(start)),
+        }
+
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(JsonError::InvalidNumber(start));
+            }
+            self.skip_digits();
+        }
+
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(JsonError::InvalidNumber(start));
+            }
+            self.skip_digits();
+        }
+
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(JsonValue::Number)
+            .ok_or(JsonError::InvalidNumber(start))
+    }
+
+    fn skip_digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+}
+
+fn write_json_string(f: &mut Formatter<'_>, string: &str) -> FmtResult {
+    f.write_char('"')?;
+    for chr in string.chars() {
+        match chr {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\u{8}' => f.write_str("\\b")?,
+            '\u{c}' => f.write_str("\\f")?,
+            chr if (chr as u32) < 0x20 => write!(f, "\\u{:04x}", chr as u32)?,
+            chr => f.write_char(chr)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl JsonValue {
+    pub fn parse<S>(input: S) -> Result<JsonValue>
+    where
+        S: AsRef<str>,
+    {
+        JsonParser::new(input.as_ref())
+            .parse_document()
+            .map_err(RebarError::JsonError)
+    }
+
+    pub(crate) fn parse_raw(input: &str) -> std::result::Result<JsonValue, JsonError> {
+        JsonParser::new(input).parse_document()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(object) => object.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn at(&self, index: usize) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Array(array) => array.get(index),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == JsonValue::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            JsonValue::Number(n) if n.fract() == 0.0 && n.abs() < 9007199254740992.0 => {
+                Some(*n as i64)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, JsonValue>> {
+        match self {
+            JsonValue::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+}
+
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            JsonValue::Null => f.write_str("null"),
+            JsonValue::Bool(b) => write!(f, "{}", b),
+            JsonValue::Number(n) if n.is_finite() => write!(f, "{}", n),
+            // json has no representation for these
+            JsonValue::Number(_) => f.write_str("null"),
+            JsonValue::String(s) => write_json_string(f, s),
+            JsonValue::Array(array) => {
+                f.write_char('[')?;
+                for (i, value) in array.iter().enumerate() {
+                    if i != 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_char(']')
+            }
+            JsonValue::Object(object) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in object.iter().enumerate() {
+                    if i != 0 {
+                        f.write_char(',')?;
+                    }
+                    write_json_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(b: bool) -> JsonValue {
+        JsonValue::Bool(b)
+    }
+}
+
+macro_rules! json_from_number {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for JsonValue {
+                fn from(n: $t) -> JsonValue {
+                    JsonValue::Number(n as f64)
+                }
+            }
+        )*
+    };
+}
+
+json_from_number!(f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl From<&str> for JsonValue {
+    fn from(s: &str) -> JsonValue {
+        JsonValue::String(s.to_owned())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(s: String) -> JsonValue {
+        JsonValue::String(s)
+    }
+}
+
+impl<T> From<Option<T>> for JsonValue
+where
+    T: Into<JsonValue>,
+{
+    fn from(option: Option<T>) -> JsonValue {
+        match option {
+            Some(value) => value.into(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for JsonValue
+where
+    T: Into<JsonValue>,
+{
+    fn from(array: Vec<T>) -> JsonValue {
+        JsonValue::Array(array.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<BTreeMap<String, JsonValue>> for JsonValue {
+    fn from(object: BTreeMap<String, JsonValue>) -> JsonValue {
+        JsonValue::Object(object)
+    }
+}
+
+#[macro_export]
+macro_rules! json_object {
+    {$($key:expr => $value:expr),* $(,)?} => {
+        {
+            {
+                #[allow(unused_mut)]
+                let mut map = std::collections::BTreeMap::new();
+                $(
+                    map.insert(
+                        std::string::String::from($key),
+                        $crate::JsonValue::from($value),
+                    );
+                )*
+                $crate::JsonValue::Object(map)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_json() {
+        assert_eq!(JsonValue::parse("null"), Ok(JsonValue::Null));
+        assert_eq!(JsonValue::parse(" true "), Ok(JsonValue::Bool(true)));
+        assert_eq!(JsonValue::parse("-12.5e1"), Ok(JsonValue::Number(-125.0)));
+        assert_eq!(
+            JsonValue::parse(r#""a\"b\\c\né😀""#),
+            Ok(JsonValue::String("a\"b\\c\né😀".into()))
+        );
+        assert_eq!(
+            JsonValue::parse(r#"{"name": "rebar", "tags": [1, 2, {"deep": null}], "ok": false}"#),
+            Ok(json_object! {
+                "name" => "rebar",
+                "tags" => vec![
+                    JsonValue::from(1),
+                    JsonValue::from(2),
+                    json_object! {"deep" => JsonValue::Null},
+                ],
+                "ok" => false,
+            })
+        );
+        assert_eq!(JsonValue::parse("[]"), Ok(JsonValue::Array(vec![])));
+        assert_eq!(JsonValue::parse("{}"), Ok(json_object! {}));
+    }
+
+    #[test]
+    fn it_fails_parsing_json() {
+        let err = |e| Err(RebarError::JsonError(e));
+
+        assert_eq!(JsonValue::parse(""), err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::parse("nul"), err(JsonError::UnexpectedEof));
+        assert_eq!(
+            JsonValue::parse("[1,]"),
+            err(JsonError::UnexpectedChar(']', 3))
+        );
+        assert_eq!(
+            JsonValue::parse("{'a': 1}"),
+            err(JsonError::UnexpectedChar('\'', 1))
+        );
+        assert_eq!(
+            JsonValue::parse("01"),
+            err(JsonError::TrailingCharacters(1))
+        );
+        assert_eq!(JsonValue::parse("1."), err(JsonError::InvalidNumber(0)));
+        assert_eq!(JsonValue::parse("-"), err(JsonError::InvalidNumber(0)));
+        assert_eq!(
+            JsonValue::parse(r#""\x""#),
+            err(JsonError::InvalidEscape(1))
+        );
+        assert_eq!(
+            JsonValue::parse(r#""\ud83d""#),
+            err(JsonError::InvalidUnicode(1))
+        );
+        assert_eq!(
+            JsonValue::parse("\"tab\there\""),
+            err(JsonError::UnexpectedChar('\t', 4))
+        );
+        assert_eq!(JsonValue::parse("\"open"), err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::parse("[".repeat(200)), err(JsonError::TooDeep));
+    }
+
+    #[test]
+    fn it_serializes_json() {
+        let value = json_object! {
+            "text" => "quote \" and\nnewline \u{1}",
+            "numbers" => vec![1.5, -2.0, f64::NAN],
+            "nothing" => None::<bool>,
+        };
+
+        let serialized = value.to_string();
+        assert_eq!(
+            serialized,
+            r#"{"nothing":null,"numbers":[1.5,-2,null],"text":"quote \" and\nnewline \u0001"}"#
+        );
+        assert_eq!(
+            JsonValue::parse(&serialized).unwrap().get("text"),
+            value.get("text")
+        );
+        assert_eq!(
+            value
+                .get("numbers")
+                .and_then(|n| n.at(1))
+                .and_then(JsonValue::as_i64),
+            Some(-2)
+        );
+    }
+}
@@ -1,8 +1,38 @@
+mod auth;
+mod base64;
+mod compress;
+mod conditional;
+mod connection;
+mod cookie;
+mod cookie_keys;
+mod cors;
+mod crypto;
+mod csrf;
+mod date;
+mod deflate;
+mod form;
+mod json;
+mod mime;
+mod multipart;
+mod negotiate;
 mod parse;
+mod proxy;
+mod range;
+mod rate_limit;
+mod request;
 mod respond;
+mod security_headers;
 mod server;
+mod session;
+mod session_store;
 mod simple_impls;
+mod static_files;
+mod status;
+mod tempfile;
 mod template;
+mod testing;
+#[cfg(feature = "tls")]
+mod tls;
 mod types;
 
 pub use types::*;
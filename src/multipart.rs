@@ -0,0 +1,498 @@
+use crate::parse::find;
+use crate::tempfile::Spool;
+use crate::types::{
+    HeaderMethods, Headers, HttpParseError, Multipart, MultipartLimits, MultipartPart,
+    MultipartState, Spooled, TempFile,
+};
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+const READ_CHUNK_SIZE: usize = 8192;
+
+fn invalid(reason: &str) -> HttpParseError {
+    HttpParseError::InvalidMultipart(reason.to_owned())
+}
+
+// splits `value; key=value; key="quoted value"` into the leading value and its parameters
+pub(crate) fn header_params(header: &str) -> (String, Vec<(String, String)>) {
+    let mut chars = header.chars().peekable();
+    let mut value = String::new();
+    while let Some(chr) = chars.next_if(|chr| *chr != ';') {
+        value.push(chr);
+    }
+
+    let mut params = Vec::new();
+    while chars.next().is_some() {
+        let mut key = String::new();
+        while let Some(chr) = chars.next_if(|chr| *chr != '=' && *chr != ';') {
+            key.push(chr);
+        }
+
+        let mut param = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|chr| chr.is_whitespace()).is_some() {}
+
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(chr) = chars.next() {
+                    match chr {
+                        '"' => break,
+                        '\\' => param.extend(chars.next()),
+                        chr => param.push(chr),
+                    }
+                }
+                while chars.next_if(|chr| *chr != ';').is_some() {}
+            } else {
+                while let Some(chr) = chars.next_if(|chr| *chr != ';') {
+                    param.push(chr);
+                }
+                param = param.trim().to_owned();
+            }
+        }
+
+        let key = key.trim().to_ascii_lowercase();
+        if !key.is_empty() {
+            params.push((key, param));
+        }
+    }
+
+    (value.trim().to_owned(), params)
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        MultipartLimits {
+            max_part_size: 8 * 1024 * 1024,
+            max_total_size: 16 * 1024 * 1024,
+            max_parts: 128,
+            max_header_size: 8 * 1024,
+            memory_threshold: 64 * 1024,
+        }
+    }
+}
+
+// the `name` and `filename` parameters of a part's `Content-Disposition`
+fn disposition(headers: &Headers) -> (Option<String>, Option<String>) {
+    let (mut name, mut filename) = (None, None);
+    if let Some(disposition) = headers.get_header("Content-Disposition") {
+        for (key, value) in header_params(disposition).1 {
+            match key.as_str() {
+                "name" => name = Some(value),
+                "filename" => filename = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    (name, filename)
+}
+
+impl<'a> Multipart<'a> {
+    pub(crate) fn new(reader: Box<dyn Read + 'a>, boundary: &str) -> Multipart<'a> {
+        Multipart {
+            reader,
+            // the opening boundary has no line break before it, so pretend it does
+            buf: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            limits: Default::default(),
+            state: MultipartState::Preamble,
+            read_total: 0,
+            parts: 0,
+        }
+    }
+
+    pub fn limits(mut self, limits: MultipartLimits) -> Multipart<'a> {
+        self.limits = limits;
+        self
+    }
+
+    fn fill(&mut self) -> Result<bool, HttpParseError> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.read_total += n as u64;
+                    if self.read_total > self.limits.max_total_size {
+                        return Err(HttpParseError::PayloadTooLarge);
+                    }
+                    self.buf.extend_from_slice(&chunk[..n]);
+
+                    return Ok(true);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(HttpParseError::Other(format!("{}", err))),
+            }
+        }
+    }
+
+    fn find_filling(&mut self, needle: &[u8], limit: usize) -> Result<usize, HttpParseError> {
+        loop {
+            if let Some(i) = find(&self.buf, needle) {
+                return Ok(i);
+            }
+            if self.buf.len() > limit {
+                return Err(HttpParseError::PayloadTooLarge);
+            }
+            if !self.fill()? {
+                return Err(invalid("Unexpected end of body"));
+            }
+        }
+    }
+
+    // reads up to the closing part boundary, handing the data over a chunk at a time
+    fn read_data<W>(&mut self, mut write: W) -> Result<(), HttpParseError>
+    where
+        W: FnMut(&[u8]) -> Result<(), HttpParseError>,
+    {
+        let keep = self.delimiter.len() - 1;
+
+        loop {
+            if let Some(i) = find(&self.buf, &self.delimiter) {
+                write(&self.buf[..i])?;
+                self.buf.drain(..i + self.delimiter.len());
+                return Ok(());
+            }
+            if self.buf.len() > keep {
+                let n = self.buf.len() - keep;
+                write(&self.buf[..n])?;
+                self.buf.drain(..n);
+            }
+            if !self.fill()? {
+                return Err(invalid("Unexpected end of body"));
+            }
+        }
+    }
+
+    fn next_headers(&mut self) -> Result<Option<Headers>, HttpParseError> {
+        let keep = self.delimiter.len() - 1;
+
+        match self.state {
+            MultipartState::Done => return Ok(None),
+            MultipartState::Preamble => {
+                loop {
+                    if let Some(i) = find(&self.buf, &self.delimiter) {
+                        self.buf.drain(..i + self.delimiter.len());
+                        break;
+                    }
+                    if self.buf.len() > keep {
+                        self.buf.drain(..self.buf.len() - keep);
+                    }
+                    if !self.fill()? {
+                        return Err(invalid("Missing opening boundary"));
+                    }
+                }
+                self.state = MultipartState::Boundary;
+            }
+            MultipartState::Boundary => {}
+        }
+
+        // a boundary is followed by either `--` ending the body or the end of its line
+        while self.buf.len() < 2 {
+            if !self.fill()? {
+                return Err(invalid("Unexpected end of body"));
+            }
+        }
+        if self.buf.starts_with(b"--") {
+            self.state = MultipartState::Done;
+            return Ok(None);
+        }
+
+        let line_end = self.find_filling(b"\r\n", self.limits.max_header_size)?;
+        if !self.buf[..line_end]
+            .iter()
+            .all(|b| *b == b' ' || *b == b'\t')
+        {
+            return Err(invalid("Malformed boundary"));
+        }
+        // keep the line break so a part without headers still ends in a blank line
+        self.buf.drain(..line_end);
+
+        self.parts += 1;
+        if self.parts > self.limits.max_parts {
+            return Err(HttpParseError::PayloadTooLarge);
+        }
+
+        let headers_end = self.find_filling(b"\r\n\r\n", self.limits.max_header_size)?;
+        let mut headers = Headers(HashMap::new());
+        if headers_end > 0 {
+            for line in String::from_utf8_lossy(&self.buf[2..headers_end]).split("\r\n") {
+                match line.split_once(':') {
+                    Some((name, value)) if !name.trim().is_empty() => {
+                        headers.set_header(name.trim(), value.trim());
+                    }
+                    _ => return Err(invalid("Malformed part header")),
+                }
+            }
+        }
+        self.buf.drain(..headers_end + 4);
+
+        Ok(Some(headers))
+    }
+
+    fn next_part(&mut self) -> Result<Option<MultipartPart>, HttpParseError> {
+        let headers = match self.next_headers()? {
+            Some(headers) => headers,
+            None => return Ok(None),
+        };
+
+        let mut spool = Spool::new(self.limits.memory_threshold);
+        let max_part_size = self.limits.max_part_size;
+        self.read_data(|data| {
+            if spool.len() + data.len() as u64 > max_part_size {
+                return Err(HttpParseError::PayloadTooLarge);
+            }
+            spool
+                .write_all(data)
+                .map_err(|err| HttpParseError::Other(format!("{}", err)))
+        })?;
+
+        let (name, filename) = disposition(&headers);
+        let content_type = headers.get_header("Content-Type").cloned();
+
+        Ok(Some(MultipartPart {
+            name,
+            filename,
+            content_type,
+            headers,
+
+            data: spool
+                .finish()
+                .map_err(|err| HttpParseError::Other(format!("{}", err)))?,
+        }))
+    }
+
+    // the text of the first part named `name`; the parts before it are skipped without being
+    // kept and nothing after it is read, so it's cheap enough to run before authentication
+    pub(crate) fn field(&mut self, name: &str) -> Result<Option<String>, HttpParseError> {
+        while let Some(headers) = self.next_headers()? {
+            if disposition(&headers).0.as_deref() != Some(name) {
+                self.read_data(|_| Ok(()))?;
+                continue;
+            }
+
+            let mut value = Vec::new();
+            let limit = self.limits.memory_threshold;
+            self.read_data(|data| {
+                if value.len() + data.len() > limit {
+                    return Err(HttpParseError::PayloadTooLarge);
+                }
+                value.extend_from_slice(data);
+                Ok(())
+            })?;
+            self.state = MultipartState::Done;
+
+            return Ok(String::from_utf8(value).ok());
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for Multipart<'_> {
+    type Item = Result<MultipartPart, HttpParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_part() {
+            Ok(part) => part.map(Ok),
+            Err(err) => {
+                self.state = MultipartState::Done;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl MultipartPart {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // only available while the part is small enough to be kept in memory
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.data {
+            Spooled::Memory(bytes) => Some(bytes),
+            Spooled::File(_) => None,
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn temp_file(&self) -> Option<&TempFile> {
+        match &self.data {
+            Spooled::Memory(_) => None,
+            Spooled::File(temp) => Some(temp),
+        }
+    }
+
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        self.data.reader()
+    }
+
+    pub fn persist<P>(self, to: P) -> io::Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        self.data.persist(to.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn multipart(body: &[u8]) -> Multipart<'_> {
+        Multipart::new(Box::new(body), "XyZ")
+    }
+
+    #[test]
+    fn it_parses_header_params() {
+        assert_eq!(
+            header_params(r#"form-data; name="field"; filename="a \"b\".txt""#),
+            (
+                "form-data".to_owned(),
+                vec![
+                    ("name".to_owned(), "field".to_owned()),
+                    ("filename".to_owned(), r#"a "b".txt"#.to_owned()),
+                ]
+            )
+        );
+        assert_eq!(
+            header_params("multipart/form-data; Boundary=abc ; flag"),
+            (
+                "multipart/form-data".to_owned(),
+                vec![
+                    ("boundary".to_owned(), "abc".to_owned()),
+                    ("flag".to_owned(), "".to_owned()),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn it_finds_single_fields() {
+        let limits = MultipartLimits {
+            max_part_size: 4,
+            memory_threshold: 4,
+            ..Default::default()
+        };
+        // the file is over both limits, but it's skipped rather than kept; nothing after
+        // the field is read, so the missing closing boundary doesn't matter
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a.bin\"\r\n\r\n0123456789\r\n--XyZ\r\nContent-Disposition: form-data; name=\"token\"\r\n\r\nabc\r\n--XyZ\r\ntruncated";
+        let field = |name: &str| multipart(body).limits(limits.clone()).field(name);
+
+        assert_eq!(field("token"), Ok(Some("abc".to_owned())));
+        assert!(field("missing").is_err());
+
+        let body =
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n--XyZ--";
+        assert_eq!(multipart(body).field("token"), Ok(None));
+        assert_eq!(
+            multipart(b"--XyZ\r\nContent-Disposition: form-data; name=\"token\"\r\n\r\n0123456789\r\n--XyZ--")
+                .limits(limits.clone())
+                .field("token"),
+            Err(HttpParseError::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn it_parses_parts() {
+        let body = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a.bin\"\r\nContent-Type: application/octet-stream\r\n\r\n\x00\x01\r\n--X\r\n--XyZ--\r\nepilogue";
+
+        let parts = multipart(body).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].name.as_deref(), Some("title"));
+        assert!(!parts[0].is_file());
+        assert_eq!(parts[0].text(), Some("hello"));
+
+        assert_eq!(parts[1].name.as_deref(), Some("upload"));
+        assert_eq!(parts[1].filename.as_deref(), Some("a.bin"));
+        assert_eq!(
+            parts[1].content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+        assert_eq!(parts[1].bytes(), Some(&b"\x00\x01\r\n--X"[..]));
+    }
+
+    #[test]
+    fn it_streams_large_parts_to_disk() {
+        let data = "0123456789".repeat(5000);
+        let body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"f\"; filename=\"big.txt\"\r\n\r\n{}\r\n--XyZ--\r\n",
+            data
+        );
+
+        let mut parts = multipart(body.as_bytes()).limits(MultipartLimits {
+            memory_threshold: 1024,
+            ..Default::default()
+        });
+        let part = parts.next().unwrap().unwrap();
+        assert!(parts.next().is_none());
+
+        assert_eq!(part.len(), data.len() as u64);
+        assert_eq!(part.bytes(), None);
+        let temp_path = part.temp_file().unwrap().path().to_path_buf();
+
+        let target = std::env::temp_dir().join(format!("rebar-test-{}", std::process::id()));
+        part.persist(&target).unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), data);
+        std::fs::remove_file(target).unwrap();
+    }
+
+    #[test]
+    fn it_enforces_multipart_limits() {
+        let body =
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n0123456789\r\n--XyZ--";
+
+        let mut parts = multipart(body).limits(MultipartLimits {
+            max_part_size: 5,
+            ..Default::default()
+        });
+        assert_eq!(parts.next(), Some(Err(HttpParseError::PayloadTooLarge)));
+        assert_eq!(parts.next(), None);
+
+        let mut parts = multipart(body).limits(MultipartLimits {
+            max_total_size: 20,
+            ..Default::default()
+        });
+        assert_eq!(parts.next(), Some(Err(HttpParseError::PayloadTooLarge)));
+
+        let mut parts = multipart(body).limits(MultipartLimits {
+            max_parts: 0,
+            ..Default::default()
+        });
+        assert_eq!(parts.next(), Some(Err(HttpParseError::PayloadTooLarge)));
+    }
+
+    #[test]
+    fn it_rejects_malformed_bodies() {
+        assert_eq!(
+            multipart(b"no boundary here").next(),
+            Some(Err(invalid("Missing opening boundary")))
+        );
+        assert_eq!(
+            multipart(b"--XyZ\r\n\r\nunterminated").next(),
+            Some(Err(invalid("Unexpected end of body")))
+        );
+        assert_eq!(
+            multipart(b"--XyZ\r\nbroken header\r\n\r\ndata\r\n--XyZ--").next(),
+            Some(Err(invalid("Malformed part header")))
+        );
+        assert_eq!(multipart(b"--XyZ--").next(), None);
+    }
+}
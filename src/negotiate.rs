@@ -0,0 +1,277 @@
+use crate::types::{HeaderMethods, Request};
+
+fn parse_quality(value: &str) -> Option<f32> {
+    let quality = value.trim().parse::<f32>().ok()?;
+
+    (0.0..=1.0).contains(&quality).then_some(quality)
+}
+
+// splits a header like `gzip;q=0.8, br, *;q=0` into lowercased values and their quality,
+// keeping any other parameters as part of the value
+pub(crate) fn quality_values(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let mut value = params.next()?.trim().to_lowercase();
+            if value.is_empty() {
+                return None;
+            }
+
+            let mut quality = 1.0;
+            for param in params.map(str::trim).filter(|param| !param.is_empty()) {
+                match param.split_once('=') {
+                    Some((name, q)) if name.trim().eq_ignore_ascii_case("q") => {
+                        quality = parse_quality(q)?
+                    }
+                    _ => {
+                        value.push(';');
+                        value.push_str(&param.to_lowercase());
+                    }
+                }
+            }
+
+            Some((value, quality))
+        })
+        .collect()
+}
+
+// most preferred first, leaving out anything refused with `q=0`
+fn preferences(header: Option<&String>) -> Vec<String> {
+    let mut values = quality_values(header.map(String::as_str).unwrap_or(""));
+    values.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    values
+        .into_iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .map(|(value, _)| value)
+        .collect()
+}
+
+// how closely an `Accept` range matches an offered type, higher is more specific
+fn media_specificity(range: &str, offer: &str) -> Option<usize> {
+    let mut range_params = range.split(';').map(str::trim);
+    let mut offer_params = offer.split(';').map(str::trim);
+    let (range_type, range_subtype) = range_params.next()?.split_once('/')?;
+    let (offer_type, offer_subtype) = offer_params.next()?.split_once('/')?;
+
+    match (range_type, range_subtype) {
+        ("*", "*") => Some(0),
+        (kind, "*") if kind == offer_type => Some(1),
+        (kind, subtype) if kind == offer_type && subtype == offer_subtype => {
+            // parameters in the range have to be present on the offer as well
+            let offer_params = offer_params.collect::<Vec<_>>();
+            let mut count = 0;
+            for param in range_params {
+                if !offer_params.contains(&param) {
+                    return None;
+                }
+                count += 1;
+            }
+
+            Some(2 + count)
+        }
+        _ => None,
+    }
+}
+
+// `en` matches `en-us` as well, as in the basic filtering of RFC 4647
+fn language_specificity(range: &str, offer: &str) -> Option<usize> {
+    if range == "*" {
+        return Some(0);
+    }
+
+    match offer.strip_prefix(range) {
+        Some("") => Some(range.len() + 1),
+        Some(rest) if rest.starts_with('-') => Some(range.len()),
+        _ => None,
+    }
+}
+
+fn charset_specificity(range: &str, offer: &str) -> Option<usize> {
+    match range {
+        "*" => Some(0),
+        range if range == offer => Some(1),
+        _ => None,
+    }
+}
+
+// each offer gets the quality of the most specific range that matches it, the best offer
+// wins and ties go to whichever came first; a missing header accepts anything
+fn best_match<'a>(
+    header: Option<&String>,
+    offered: &[&'a str],
+    specificity: fn(&str, &str) -> Option<usize>,
+) -> Option<&'a str> {
+    let header = match header {
+        Some(header) => header,
+        None => return offered.first().copied(),
+    };
+    let ranges = quality_values(header);
+
+    let mut best: Option<(&str, f32)> = None;
+    for offer in offered {
+        let normalized = offer
+            .split(';')
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(";")
+            .to_lowercase();
+
+        let quality = ranges
+            .iter()
+            .filter_map(|(range, quality)| Some((specificity(range, &normalized)?, *quality)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.0);
+
+        let better = match best {
+            Some((_, best)) => quality > best,
+            None => true,
+        };
+        if quality > 0.0 && better {
+            best = Some((offer, quality));
+        }
+    }
+
+    best.map(|(offer, _)| offer)
+}
+
+impl Request {
+    pub fn accepted_types(&self) -> Vec<String> {
+        preferences(self.headers.get_header("Accept"))
+    }
+
+    pub fn accepted_languages(&self) -> Vec<String> {
+        preferences(self.headers.get_header("Accept-Language"))
+    }
+
+    pub fn accepted_charsets(&self) -> Vec<String> {
+        preferences(self.headers.get_header("Accept-Charset"))
+    }
+
+    // picks the media type from `offered` the client prefers, `None` means
+    // nothing is acceptable and the answer should be a 406
+    pub fn negotiate<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        best_match(
+            self.headers.get_header("Accept"),
+            offered,
+            media_specificity,
+        )
+    }
+
+    pub fn negotiate_language<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        best_match(
+            self.headers.get_header("Accept-Language"),
+            offered,
+            language_specificity,
+        )
+    }
+
+    pub fn negotiate_charset<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        best_match(
+            self.headers.get_header("Accept-Charset"),
+            offered,
+            charset_specificity,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_quality_values() {
+        assert_eq!(
+            quality_values("gzip;q=0.8, BR , *;q=0,, deflate; q=1.5, x;level=1;Q=0.5"),
+            vec![
+                ("gzip".to_owned(), 0.8),
+                ("br".to_owned(), 1.0),
+                ("*".to_owned(), 0.0),
+                ("x;level=1".to_owned(), 0.5),
+            ]
+        );
+        assert!(quality_values("").is_empty());
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut req = Request::default();
+        for (name, value) in headers {
+            req.headers.set_header(*name, *value);
+        }
+
+        req
+    }
+
+    #[test]
+    fn it_orders_preferences() {
+        let req = request(&[
+            (
+                "Accept",
+                "text/*;q=0.3, text/html;q=0.7, application/json, image/png;q=0",
+            ),
+            (
+                "Accept-Language",
+                "fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5",
+            ),
+        ]);
+        assert_eq!(
+            req.accepted_types(),
+            vec!["application/json", "text/html", "text/*"]
+        );
+        assert_eq!(
+            req.accepted_languages(),
+            vec!["fr-ch", "fr", "en", "de", "*"]
+        );
+        assert!(req.accepted_charsets().is_empty());
+    }
+
+    #[test]
+    fn it_negotiates() {
+        let offered = ["text/html", "application/json"];
+
+        assert_eq!(request(&[]).negotiate(&offered), Some("text/html"));
+        assert_eq!(request(&[]).negotiate(&[]), None);
+
+        let negotiate = |accept: &str| request(&[("Accept", accept)]).negotiate(&offered);
+        assert_eq!(negotiate("application/json"), Some("application/json"));
+        assert_eq!(negotiate("*/*"), Some("text/html"));
+        assert_eq!(
+            negotiate("text/*;q=0.5, */*;q=0.8"),
+            Some("application/json")
+        );
+        assert_eq!(
+            negotiate("application/json;q=0.9, text/html;q=0.8"),
+            Some("application/json")
+        );
+        // the more specific range decides, even when it's lower
+        assert_eq!(negotiate("*/*, text/html;q=0"), Some("application/json"));
+        assert_eq!(negotiate("TEXT/HTML"), Some("text/html"));
+        assert_eq!(negotiate("image/png"), None);
+        assert_eq!(negotiate("text/html;level=1"), None);
+        assert_eq!(
+            request(&[("Accept", "text/html;level=1")]).negotiate(&["text/html; Level=1"]),
+            Some("text/html; Level=1")
+        );
+
+        let language = |accept: &str, offered: &[&'static str]| {
+            request(&[("Accept-Language", accept)]).negotiate_language(offered)
+        };
+        assert_eq!(language("en", &["de", "en-US"]), Some("en-US"));
+        assert_eq!(
+            language("en-gb, en;q=0.8", &["en-US", "en-GB"]),
+            Some("en-GB")
+        );
+        assert_eq!(language("en-gb", &["en"]), None);
+        assert_eq!(language("*;q=0.1, de", &["fr", "de"]), Some("de"));
+        assert_eq!(language("*, fr;q=0", &["fr", "es"]), Some("es"));
+
+        let charset = |accept: &str| {
+            request(&[("Accept-Charset", accept)]).negotiate_charset(&["utf-8", "iso-8859-1"])
+        };
+        assert_eq!(charset("ISO-8859-1, utf-8;q=0.5"), Some("iso-8859-1"));
+        assert_eq!(charset("*"), Some("utf-8"));
+        assert_eq!(charset("ascii"), None);
+    }
+}
@@ -1,39 +1,107 @@
+use crate::deflate::decompress;
+use crate::form::parse_urlencoded;
+use crate::tempfile::Spool;
 use crate::types::{
-    HeaderMethods, Headers, HttpParseError, HttpVersion, Method, NormalizePath, Request,
+    ContentCoding, HeaderMethods, Headers, HttpParseError, HttpStatusCode, HttpVersion, Method,
+    NormalizePath, ParseConfig, Request, Spooled,
 };
 
 use std::collections::HashMap;
-use std::io::Read;
-use std::net::TcpStream;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+
+const READ_CHUNK_SIZE: usize = 8192;
+
+impl Default for ParseConfig {
+    fn default() -> ParseConfig {
+        ParseConfig {
+            max_head_size: 16 * 1024,
+            max_body_size: 16 * 1024 * 1024,
+            body_memory_limit: 1024 * 1024,
+            decompress_bodies: false,
+            max_decompressed_size: 16 * 1024 * 1024,
+            trusted_proxies: vec![],
+            trust_addressless_peers: false,
+        }
+    }
+}
 
-pub(crate) fn parse(stream: &mut TcpStream) -> Result<Request, HttpParseError> {
-    let mut buf = [0u8; 4096];
+impl HttpParseError {
+    pub fn status(&self) -> HttpStatusCode {
+        match self {
+            HttpParseError::HeadersTooLarge => HttpStatusCode::Code431,
+            HttpParseError::PayloadTooLarge => HttpStatusCode::Code413,
+            HttpParseError::UnsupportedEncoding(_) => HttpStatusCode::Code415,
+            HttpParseError::UnsupportedMediaType(_) => HttpStatusCode::Code415,
+            _ => HttpStatusCode::Code400,
+        }
+    }
+}
 
-    match stream.read(&mut buf) {
-        Err(err) => Err(HttpParseError::Other(format!("{}", err))),
-        Ok(_) => Ok(internal_parse(String::from_utf8_lossy(&buf).into_owned())?),
+fn to_http_parse_error(err: std::io::Error) -> HttpParseError {
+    match err.kind() {
+        ErrorKind::InvalidData => HttpParseError::InvalidChunkedEncoding,
+        _ => HttpParseError::Other(format!("{}", err)),
     }
 }
 
-fn internal_parse(req: String) -> Result<Request, HttpParseError> {
-    let mut block_iter = req.splitn(2, "\r\n\r\n");
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
 
-    let head = match block_iter.next() {
-        Some(s) => s,
+pub(crate) fn parse<S>(stream: &mut S, config: &ParseConfig) -> Result<Request, HttpParseError>
+where
+    S: Read + Write,
+{
+    let (head, leftover) = read_head(stream, config.max_head_size)?;
+    let mut req = parse_head(&String::from_utf8_lossy(&head))?;
 
-        // it's empty so we simply say the method is invalid as it doesnt exist
-        None => return Err(HttpParseError::InvalidMethod),
-    };
+    read_body(stream, &leftover, &mut req, config)?;
 
-    let body: Option<String> = match block_iter.next() {
-        Some(x) if x.trim() != "" => Some(x.trim().trim_end_matches(char::from(0)).to_owned()),
-        _ => None,
-    };
+    Ok(req)
+}
 
+// returns the head without the blank line, and whatever part of the body was read along with it
+fn read_head<R>(stream: &mut R, max_size: usize) -> Result<(Vec<u8>, Vec<u8>), HttpParseError>
+where
+    R: Read,
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    let mut searched = 0;
+
+    loop {
+        if let Some(end) = find(&buf[searched..], b"\r\n\r\n") {
+            let end = searched + end;
+            if end > max_size {
+                return Err(HttpParseError::HeadersTooLarge);
+            }
+            let body = buf.split_off(end + 4);
+            buf.truncate(end);
+
+            return Ok((buf, body));
+        }
+        if buf.len() > max_size {
+            return Err(HttpParseError::HeadersTooLarge);
+        }
+        searched = buf.len().saturating_sub(3);
+
+        match stream.read(&mut chunk) {
+            // the client stopped sending, so treat what we have as the whole head
+            Ok(0) => return Ok((buf, vec![])),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(to_http_parse_error(err)),
+        }
+    }
+}
+
+fn parse_head(head: &str) -> Result<Request, HttpParseError> {
     let mut head_iter = head.split("\r\n");
 
     let mut strings = match head_iter.next() {
-        Some(req_line) => req_line.split(" "),
+        Some(req_line) => req_line.split(' '),
         None => return Err(HttpParseError::InvalidMethod),
     };
 
@@ -45,18 +113,14 @@ fn internal_parse(req: String) -> Result<Request, HttpParseError> {
 
     let mut headers = Headers(HashMap::new());
 
-    while let Some(header_line) = head_iter.next() {
-        if header_line.len() == 0 {
+    for header_line in head_iter {
+        if header_line.is_empty() {
             break;
         }
-        let mut header_iter = header_line.split(':');
-
-        let header_name = match header_iter.next() {
-            Some(s) if s.trim() != "" => s.trim(),
-            _ => return Err(HttpParseError::InvalidHeaderSyntax),
-        };
-        let header_value = match header_iter.next() {
-            Some(s) if s.trim() != "" => s.trim(),
+        let (header_name, header_value) = match header_line.split_once(':') {
+            Some((name, value)) if name.trim() != "" && value.trim() != "" => {
+                (name.trim(), value.trim())
+            }
             _ => return Err(HttpParseError::InvalidHeaderSyntax),
         };
 
@@ -72,10 +136,221 @@ fn internal_parse(req: String) -> Result<Request, HttpParseError> {
 
         headers,
 
-        body,
+        ..Default::default()
     })
 }
 
+fn read_body<S>(
+    stream: &mut S,
+    leftover: &[u8],
+    req: &mut Request,
+    config: &ParseConfig,
+) -> Result<(), HttpParseError>
+where
+    S: Read + Write,
+{
+    // without chunked as the last coding there's no telling where the body ends, falling
+    // back to Content-Length would read it differently from proxies that reject it
+    let chunked = match req.headers.get_header("Transfer-Encoding") {
+        Some(encoding) => {
+            let last = encoding.rsplit(',').next().unwrap_or("");
+            if !last.trim().eq_ignore_ascii_case("chunked") {
+                return Err(HttpParseError::InvalidChunkedEncoding);
+            }
+            true
+        }
+        None => false,
+    };
+    let length = match req.headers.get_header("Content-Length") {
+        Some(length) => Some(
+            length
+                .parse::<u64>()
+                .map_err(|_| HttpParseError::InvalidContentLength)?,
+        ),
+        None => None,
+    };
+
+    if !chunked && length.unwrap_or(0) == 0 {
+        return Ok(());
+    }
+    if length.unwrap_or(0) > config.max_body_size {
+        return Err(HttpParseError::PayloadTooLarge);
+    }
+
+    // clients that asked wait for this before they send the body
+    if let Some(expect) = req.headers.get_header("Expect") {
+        if expect.eq_ignore_ascii_case("100-continue") {
+            stream
+                .write_all(format!("{} 100 Continue\r\n\r\n", req.http_version).as_bytes())
+                .and_then(|_| stream.flush())
+                .map_err(to_http_parse_error)?;
+        }
+    }
+
+    let source = leftover.chain(stream);
+    let mut body: Box<dyn Read + '_> = if chunked {
+        Box::new(ChunkedReader::new(source))
+    } else {
+        Box::new(source.take(length.unwrap_or(0)))
+    };
+
+    let mut spool = Spool::new(config.body_memory_limit);
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    loop {
+        let n = match body.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(to_http_parse_error(err)),
+        };
+
+        if spool.len() + n as u64 > config.max_body_size {
+            return Err(HttpParseError::PayloadTooLarge);
+        }
+        spool.write_all(&chunk[..n]).map_err(to_http_parse_error)?;
+    }
+
+    if !chunked && Some(spool.len()) != length {
+        return Err(HttpParseError::Other("Unexpected end of body".into()));
+    }
+
+    let mut body = spool.finish().map_err(to_http_parse_error)?;
+    if config.decompress_bodies {
+        body = decode_body(req, body, config)?;
+    }
+    req.set_body(body);
+
+    Ok(())
+}
+
+// undoes `Content-Encoding` so handlers see the body the client meant to send
+fn decode_body(
+    req: &mut Request,
+    body: Spooled,
+    config: &ParseConfig,
+) -> Result<Spooled, HttpParseError> {
+    let header = match req.headers.get_header("Content-Encoding") {
+        Some(header) => header,
+        None => return Ok(body),
+    };
+
+    let mut codings = Vec::new();
+    for coding in header.split(',').map(str::trim) {
+        match coding.to_ascii_lowercase().as_str() {
+            "" | "identity" => {}
+            "gzip" | "x-gzip" => codings.push(ContentCoding::Gzip),
+            "deflate" => codings.push(ContentCoding::Deflate),
+            _ => return Err(HttpParseError::UnsupportedEncoding(coding.to_owned())),
+        }
+    }
+
+    // codings are listed in the order they were applied
+    let mut body = body;
+    for coding in codings.into_iter().rev() {
+        let mut spool = Spool::new(config.body_memory_limit);
+        let mut too_large = false;
+
+        let decoded = body.reader().and_then(|reader| {
+            decompress(reader, coding, &mut |data| {
+                // checked as the output grows so a small bomb can't fill the disk
+                if spool.len() + data.len() as u64 > config.max_decompressed_size {
+                    too_large = true;
+                    return Err(ErrorKind::Other.into());
+                }
+                spool.write_all(data)
+            })
+        });
+
+        match decoded {
+            Ok(_) => body = spool.finish().map_err(to_http_parse_error)?,
+            Err(_) if too_large => return Err(HttpParseError::PayloadTooLarge),
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                return Err(HttpParseError::InvalidEncodedBody)
+            }
+            Err(err) => return Err(to_http_parse_error(err)),
+        }
+    }
+
+    req.headers.remove_header("Content-Encoding");
+    if req.headers.get_header("Content-Length").is_some() {
+        req.headers
+            .set_header("Content-Length".to_owned(), body.len().to_string());
+    }
+
+    Ok(body)
+}
+
+struct ChunkedReader<R> {
+    reader: BufReader<R>,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R> ChunkedReader<R>
+where
+    R: Read,
+{
+    fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader: BufReader::new(reader),
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn read_line(&mut self) -> std::io::Result<String> {
+        let mut line = Vec::new();
+        (&mut self.reader)
+            .take(READ_CHUNK_SIZE as u64)
+            .read_until(b'\n', &mut line)?;
+
+        match line.strip_suffix(b"\r\n") {
+            Some(line) => Ok(String::from_utf8_lossy(line).into_owned()),
+            None => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+impl<R> Read for ChunkedReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            let line = self.read_line()?;
+            // chunk extensions are allowed after the size, but we have no use for them
+            let size = line.split(';').next().unwrap_or("").trim();
+            self.remaining = u64::from_str_radix(size, 16)
+                .map_err(|_| std::io::Error::from(ErrorKind::InvalidData))?;
+
+            if self.remaining == 0 {
+                // skip any trailers up to the final empty line
+                while !self.read_line()?.is_empty() {}
+                self.done = true;
+
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining as usize);
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining -= n as u64;
+        if self.remaining == 0 && !self.read_line()?.is_empty() {
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        Ok(n)
+    }
+}
+
 fn get_http_version(version: Option<&str>) -> Result<HttpVersion, HttpParseError> {
     match version {
         Some("HTTP/1.1") => Ok(HttpVersion::Http1_1),
@@ -88,17 +363,8 @@ fn parse_query(query_str: Option<String>) -> HashMap<String, String> {
     let mut query = HashMap::new();
 
     if let Some(query_str) = query_str {
-        let params = query_str.split('&');
-        for param in params {
-            let mut param_iter = param.split('=');
-            if let Some(param_name) = param_iter.next() {
-                let param_name = param_name.trim();
-                if param_name.len() == 0 {
-                    continue;
-                }
-                let param_value = param_iter.next().or_else(|| Some("")).unwrap();
-                query.entry(param_name.into()).or_insert(param_value.into());
-            }
+        for (param_name, param_value) in parse_urlencoded(&query_str) {
+            query.entry(param_name).or_insert(param_value);
         }
     }
 
@@ -108,7 +374,7 @@ fn parse_query(query_str: Option<String>) -> HashMap<String, String> {
 fn get_path(req: Option<&str>) -> Result<(String, Option<String>, Option<String>), HttpParseError> {
     let string = req.ok_or(HttpParseError::InvalidPath)?.to_owned();
 
-    if string.len() == 0 {
+    if string.is_empty() {
         Err(HttpParseError::InvalidPath)
     } else {
         Ok(string.normalize())
@@ -132,6 +398,42 @@ fn get_method(method: Option<&str>) -> Result<Method, HttpParseError> {
 mod tests {
     use super::*;
 
+    use crate::deflate::{gzip, zlib};
+
+    struct Pipe<'a> {
+        input: &'a [u8],
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn parse_with(req: &[u8], config: &ParseConfig) -> (Result<Request, HttpParseError>, Vec<u8>) {
+        let mut pipe = Pipe {
+            input: req,
+            output: vec![],
+        };
+
+        (parse(&mut pipe, config), pipe.output)
+    }
+
+    fn internal_parse(req: String) -> Result<Request, HttpParseError> {
+        parse_with(req.as_bytes(), &ParseConfig::default()).0
+    }
+
     #[test]
     fn it_gets_method() {
         assert_eq!(get_method(None), Err(HttpParseError::InvalidMethod));
@@ -225,6 +527,7 @@ mod tests {
                 headers: Headers(HashMap::new()),
 
                 body: None,
+                ..Default::default()
             })
         );
 
@@ -240,6 +543,7 @@ mod tests {
                 headers: Headers(HashMap::new()),
 
                 body: None,
+                ..Default::default()
             })
         );
 
@@ -253,6 +557,7 @@ mod tests {
                 http_version: HttpVersion::Http1_1,
                 headers: Headers(HashMap::new()),
                 body: None,
+                ..Default::default()
             })
         );
 
@@ -268,6 +573,7 @@ mod tests {
                 headers: Headers(HashMap::new()),
 
                 body: None,
+                ..Default::default()
             })
         );
 
@@ -283,6 +589,7 @@ mod tests {
                 headers: Headers(HashMap::new()),
 
                 body: None,
+                ..Default::default()
             })
         );
 
@@ -298,6 +605,7 @@ mod tests {
                 headers: Headers(HashMap::new()),
 
                 body: None,
+                ..Default::default()
             })
         );
 
@@ -313,6 +621,7 @@ mod tests {
                 headers: Headers(HashMap::new()),
 
                 body: None,
+                ..Default::default()
             })
         );
 
@@ -328,6 +637,7 @@ mod tests {
                 headers: Headers(HashMap::new()),
 
                 body: None,
+                ..Default::default()
             })
         );
     }
@@ -354,17 +664,19 @@ mod tests {
                 headers,
 
                 body: None,
+                ..Default::default()
             } )
         );
 
         let mut headers = Headers(HashMap::new());
         headers
             .set_header("Content-Type", "text/html; charset=utf-8")
-            .set_header("Host", "www.example.com");
+            .set_header("Host", "www.example.com")
+            .set_header("Content-Length", "9");
 
         assert_eq!(
             internal_parse(
-                "POST /path?ok=1 HTTP/1.1\r\nContent-Type:text/html; charset=utf-8\r\nHost: www.example.com\r\n\r\nok\r\n\r\nhmm"
+                "POST /path?ok=1 HTTP/1.1\r\nContent-Type:text/html; charset=utf-8\r\nHost: www.example.com\r\nContent-Length: 9\r\n\r\nok\r\n\r\nhmm"
                     .to_owned()
             ),
             Ok(Request {
@@ -377,7 +689,183 @@ mod tests {
                 headers,
 
                 body: Some("ok\r\n\r\nhmm".to_owned()),
+                ..Default::default()
             } )
         );
     }
+
+    #[test]
+    fn it_reads_bodies_by_length() {
+        let req = internal_parse(
+            "POST / HTTP/1.1\r\nHost: example.com:8080\r\nContent-Length: 5\r\n\r\nhello, and more"
+                .to_owned(),
+        )
+        .unwrap();
+        assert_eq!(
+            req.headers.get_header("host"),
+            Some(&"example.com:8080".to_owned())
+        );
+        assert_eq!(req.body, Some("hello".to_owned()));
+
+        assert_eq!(
+            internal_parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort".to_owned()),
+            Err(HttpParseError::Other("Unexpected end of body".to_owned()))
+        );
+        assert_eq!(
+            internal_parse("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n".to_owned()),
+            Err(HttpParseError::InvalidContentLength)
+        );
+    }
+
+    #[test]
+    fn it_reads_binary_bodies() {
+        let mut raw = b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[0, 159, 146, 150]);
+
+        let req = parse_with(&raw, &ParseConfig::default()).0.unwrap();
+        assert_eq!(req.body_bytes(), Some(&[0, 159, 146, 150][..]));
+        assert_eq!(req.body, Some("\0\u{fffd}\u{fffd}\u{fffd}".to_owned()));
+    }
+
+    #[test]
+    fn it_reads_chunked_bodies() {
+        let req = internal_parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+        assert_eq!(req.body, Some("hello, world".to_owned()));
+
+        assert_eq!(
+            internal_parse(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nz\r\nhello\r\n0\r\n\r\n"
+                    .to_owned()
+            ),
+            Err(HttpParseError::InvalidChunkedEncoding)
+        );
+    }
+
+    #[test]
+    fn it_rejects_bodies_without_chunked_last() {
+        // a proxy that goes by Content-Length would see a different request here
+        let req = internal_parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\nContent-Length: 5\r\n\r\nhello"
+                .to_owned(),
+        );
+        assert_eq!(req, Err(HttpParseError::InvalidChunkedEncoding));
+        assert_eq!(req.unwrap_err().status(), HttpStatusCode::Code400);
+
+        assert_eq!(
+            internal_parse("POST / HTTP/1.1\r\nTransfer-Encoding: identity\r\n\r\n".to_owned()),
+            Err(HttpParseError::InvalidChunkedEncoding)
+        );
+        assert!(internal_parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, Chunked\r\n\r\n0\r\n\r\n".to_owned()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn it_enforces_limits() {
+        let config = ParseConfig {
+            max_head_size: 64,
+            max_body_size: 8,
+            body_memory_limit: 4,
+            ..Default::default()
+        };
+
+        let long_header = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(100));
+        let (req, _) = parse_with(long_header.as_bytes(), &config);
+        assert_eq!(req, Err(HttpParseError::HeadersTooLarge));
+        assert_eq!(req.unwrap_err().status(), HttpStatusCode::Code431);
+
+        let (req, _) = parse_with(
+            b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n123456789",
+            &config,
+        );
+        assert_eq!(req, Err(HttpParseError::PayloadTooLarge));
+        assert_eq!(req.unwrap_err().status(), HttpStatusCode::Code413);
+
+        let (req, _) = parse_with(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n9\r\n123456789\r\n0\r\n\r\n",
+            &config,
+        );
+        assert_eq!(req, Err(HttpParseError::PayloadTooLarge));
+
+        // bodies over the memory limit end up on disk
+        let (req, _) = parse_with(
+            b"POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678",
+            &config,
+        );
+        let req = req.unwrap();
+        assert_eq!(req.body, None);
+        assert_eq!(req.body_bytes(), None);
+
+        let mut body = String::new();
+        req.body_reader()
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "12345678");
+    }
+
+    #[test]
+    fn it_decompresses_bodies() {
+        let config = ParseConfig {
+            decompress_bodies: true,
+            max_decompressed_size: 1000,
+            ..Default::default()
+        };
+        let post = |encoding: &str, body: &[u8], config: &ParseConfig| {
+            let mut req = format!(
+                "POST / HTTP/1.1\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+                encoding,
+                body.len()
+            )
+            .into_bytes();
+            req.extend(body);
+
+            parse_with(&req, config).0
+        };
+
+        let req = post("gzip", &gzip(b"name=rebar"), &config).unwrap();
+        assert_eq!(req.body.as_deref(), Some("name=rebar"));
+        assert_eq!(req.headers.get_header("Content-Encoding"), None);
+        assert_eq!(
+            req.headers.get_header("Content-Length").map(String::as_str),
+            Some("10")
+        );
+
+        // codings are undone last to first
+        let req = post("deflate, identity, x-gzip", &gzip(&zlib(b"twice")), &config).unwrap();
+        assert_eq!(req.body.as_deref(), Some("twice"));
+
+        let err = post("br", b"whatever", &config).unwrap_err();
+        assert_eq!(err, HttpParseError::UnsupportedEncoding("br".to_owned()));
+        assert_eq!(err.status(), HttpStatusCode::Code415);
+
+        let err = post("gzip", b"not gzip", &config).unwrap_err();
+        assert_eq!(err, HttpParseError::InvalidEncodedBody);
+        assert_eq!(err.status(), HttpStatusCode::Code400);
+
+        assert_eq!(
+            post("gzip", &gzip(&[b'a'; 1001]), &config),
+            Err(HttpParseError::PayloadTooLarge)
+        );
+
+        // left alone unless turned on
+        let req = post("gzip", &gzip(b"raw"), &ParseConfig::default()).unwrap();
+        assert_eq!(req.body_bytes(), Some(&gzip(b"raw")[..]));
+        assert!(req.headers.get_header("Content-Encoding").is_some());
+    }
+
+    #[test]
+    fn it_sends_continue() {
+        let (req, output) = parse_with(
+            b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nok",
+            &ParseConfig::default(),
+        );
+        assert_eq!(req.unwrap().body, Some("ok".to_owned()));
+        assert_eq!(output, b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
 }
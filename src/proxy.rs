@@ -0,0 +1,228 @@
+use crate::types::{HeaderMethods, Request, TrustedProxy};
+
+use std::net::{IpAddr, SocketAddr};
+
+impl TrustedProxy {
+    // a single address or a cidr block like `10.0.0.0/8` or `fd00::/8`
+    pub(crate) fn parse(proxy: &str) -> Option<TrustedProxy> {
+        let (network, prefix) = match proxy.trim().split_once('/') {
+            Some((network, prefix)) => (network.parse::<IpAddr>().ok()?, prefix.parse().ok()?),
+            None => {
+                let network = proxy.trim().parse::<IpAddr>().ok()?;
+                (network, if network.is_ipv4() { 32 } else { 128 })
+            }
+        };
+
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        (prefix <= bits).then_some(TrustedProxy { network, prefix })
+    }
+
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        let mask = |bits: u32| match self.prefix {
+            0 => 0,
+            prefix => u128::MAX << (bits - u32::from(prefix)),
+        };
+
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// a `for=` node from `Forwarded` or an entry from `X-Forwarded-For`, with any port removed
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok()
+}
+
+// the addresses the request was forwarded for, the client first and the closest proxy last;
+// `None` stands for a node that isn't an ip, like `unknown` or an obfuscated name
+fn forwarded_chain(req: &Request) -> Vec<Option<IpAddr>> {
+    if let Some(forwarded) = req.headers.get_header("Forwarded") {
+        return forwarded
+            .split(',')
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect();
+    }
+
+    match req.headers.get_header("X-Forwarded-For") {
+        Some(forwarded_for) => forwarded_for.split(',').map(parse_node).collect(),
+        None => vec![],
+    }
+}
+
+// walks back from the connection through trusted proxies only, so a client can't
+// pick its own address by sending a forged header
+pub(crate) fn client_ip(
+    req: &Request,
+    trusted: &[TrustedProxy],
+    trust_addressless: bool,
+) -> Option<IpAddr> {
+    let mut chain = forwarded_chain(req).into_iter().rev();
+    let mut ip = match req.remote_addr {
+        Some(addr) => addr.ip().to_canonical(),
+        // a peer without an address, like one on a unix socket, can only vouch for the
+        // closest forwarded node when it was explicitly trusted to
+        None if trust_addressless => chain.next()??.to_canonical(),
+        None => return None,
+    };
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|proxy| proxy.contains(ip));
+
+    for node in chain {
+        if !is_trusted(ip) {
+            break;
+        }
+        match node {
+            Some(node) => ip = node.to_canonical(),
+            None => break,
+        }
+    }
+
+    Some(ip)
+}
+
+impl Request {
+    // the address of the client, looking past trusted proxies; without any configured
+    // this is the ip of `remote_addr`, and over a unix socket it's `None` unless the server
+    // trusts peers without an address
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(remote: &str, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request {
+            remote_addr: remote.parse().ok(),
+            ..Default::default()
+        };
+        for (name, value) in headers {
+            req.headers.set_header(*name, *value);
+        }
+
+        req
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        ip.parse().ok()
+    }
+
+    #[test]
+    fn it_matches_cidr_blocks() {
+        let block = TrustedProxy::parse("10.1.0.0/16").unwrap();
+        assert!(block.contains("10.1.200.3".parse().unwrap()));
+        assert!(!block.contains("10.2.0.1".parse().unwrap()));
+        assert!(block.contains("::ffff:10.1.0.9".parse().unwrap()));
+
+        let single = TrustedProxy::parse("2001:db8::1").unwrap();
+        assert_eq!(single.prefix, 128);
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+
+        assert!(TrustedProxy::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert_eq!(TrustedProxy::parse("10.0.0.0/33"), None);
+        assert_eq!(TrustedProxy::parse("localhost"), None);
+    }
+
+    #[test]
+    fn it_finds_the_client_ip() {
+        let trusted = [
+            TrustedProxy::parse("127.0.0.1").unwrap(),
+            TrustedProxy::parse("10.0.0.0/8").unwrap(),
+        ];
+        let forwarded_for = |remote: &str, header: &str| {
+            client_ip(
+                &request(remote, &[("X-Forwarded-For", header)]),
+                &trusted,
+                false,
+            )
+        };
+
+        assert_eq!(client_ip(&Request::default(), &trusted, false), None);
+        assert_eq!(
+            client_ip(&request("203.0.113.9:5000", &[]), &trusted, false),
+            ip("203.0.113.9")
+        );
+
+        assert_eq!(
+            forwarded_for("127.0.0.1:5000", "198.51.100.7, 10.0.0.2"),
+            ip("198.51.100.7")
+        );
+        // whatever the client put in front of the proxies' entries is ignored
+        assert_eq!(
+            forwarded_for("127.0.0.1:5000", "6.6.6.6, 198.51.100.7, 10.0.0.2"),
+            ip("198.51.100.7")
+        );
+        // untrusted peers can't claim to be forwarding
+        assert_eq!(
+            forwarded_for("203.0.113.9:5000", "198.51.100.7"),
+            ip("203.0.113.9")
+        );
+        assert_eq!(
+            forwarded_for("127.0.0.1:5000", "unknown, 10.0.0.2"),
+            ip("10.0.0.2")
+        );
+        assert_eq!(
+            client_ip(
+                &request("127.0.0.1:5000", &[("X-Forwarded-For", "198.51.100.7")]),
+                &[],
+                false
+            ),
+            ip("127.0.0.1")
+        );
+
+        let forwarded = request(
+            "10.0.0.2:5000",
+            &[(
+                "Forwarded",
+                "for=\"[2001:db8:cafe::17]:4711\";proto=https, For=10.0.0.3;by=10.0.0.2",
+            )],
+        );
+        assert_eq!(
+            client_ip(&forwarded, &trusted, false),
+            ip("2001:db8:cafe::17")
+        );
+
+        // a unix socket peer has no address of its own, so by default what it sends is
+        // ignored and anyone who can connect can't pick their ip
+        let unix = |header: &str| request("", &[("X-Forwarded-For", header)]);
+        assert_eq!(client_ip(&unix("198.51.100.7"), &[], false), None);
+        assert_eq!(client_ip(&unix("198.51.100.7"), &trusted, false), None);
+
+        assert_eq!(
+            client_ip(&unix("198.51.100.7"), &[], true),
+            ip("198.51.100.7")
+        );
+        assert_eq!(
+            client_ip(&unix("198.51.100.7, 10.0.0.2"), &trusted, true),
+            ip("198.51.100.7")
+        );
+        assert_eq!(client_ip(&unix("unknown"), &trusted, true), None);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::base64;
+use crate::conditional::{etag_matches, truncate};
+use crate::crypto::random_bytes;
+use crate::date::parse_http_date;
+use crate::types::{ByteRanges, HeaderMethods, HttpStatusCode, Method, Request, Response};
+
+use std::io;
+use std::time::SystemTime;
+
+// more ranges than this are usually an attempt to make the server do a lot of work
+const MAX_RANGES: usize = 32;
+
+// parses a `Range` header into sorted, merged and inclusive `(first, last)` byte positions
+pub(crate) fn parse_ranges(header: &str, len: u64) -> ByteRanges {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return ByteRanges::Ignore,
+    };
+
+    let mut ranges = Vec::new();
+    for (i, spec) in specs.split(',').map(str::trim).enumerate() {
+        if i == MAX_RANGES {
+            return ByteRanges::Ignore;
+        }
+
+        let (first, last) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return ByteRanges::Ignore,
+        };
+        let parse = |n: &str| match n.bytes().all(|b| b.is_ascii_digit()) {
+            true => n.parse::<u64>().ok(),
+            false => None,
+        };
+
+        let range = match (first, last) {
+            ("", suffix) => match parse(suffix) {
+                Some(0) => None,
+                Some(suffix) if len > 0 => Some((len.saturating_sub(suffix), len - 1)),
+                Some(_) => None,
+                None => return ByteRanges::Ignore,
+            },
+            (first, "") => match parse(first) {
+                Some(first) if first < len => Some((first, len - 1)),
+                Some(_) => None,
+                None => return ByteRanges::Ignore,
+            },
+            (first, last) => match (parse(first), parse(last)) {
+                (Some(first), Some(last)) if last < first => return ByteRanges::Ignore,
+                (Some(first), Some(last)) if first < len => Some((first, last.min(len - 1))),
+                (Some(_), Some(_)) => None,
+                _ => return ByteRanges::Ignore,
+            },
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        return ByteRanges::Unsatisfiable;
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1.saturating_add(1) => {
+                previous.1 = previous.1.max(last)
+            }
+            _ => merged.push((first, last)),
+        }
+    }
+
+    ByteRanges::Satisfiable(merged)
+}
+
+impl Request {
+    // `If-Range` only lets the range through if the representation hasn't changed,
+    // otherwise the whole thing is sent like there was no `Range` at all
+    pub(crate) fn byte_ranges(
+        &self,
+        len: u64,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> ByteRanges {
+        let header = match self.headers.get_header("Range") {
+            Some(header) if self.method == Method::Get => header,
+            _ => return ByteRanges::Ignore,
+        };
+
+        if let Some(condition) = self.headers.get_header("If-Range") {
+            let condition = condition.trim();
+            let unchanged = if condition.starts_with('"') || condition.starts_with("W/") {
+                etag_matches(condition, etag, false)
+            } else {
+                match (parse_http_date(condition), last_modified) {
+                    (Some(date), Some(modified)) => truncate(modified) == date,
+                    _ => false,
+                }
+            };
+
+            if !unchanged {
+                return ByteRanges::Ignore;
+            }
+        }
+
+        parse_ranges(header, len)
+    }
+}
+
+impl Response {
+    // `read` is asked for each `(first, last)` range, so only those bytes have to be loaded
+    pub(crate) fn send_ranges<R>(
+        &mut self,
+        ranges: ByteRanges,
+        len: u64,
+        content_type: Option<String>,
+        mut read: R,
+    ) -> io::Result<Option<HttpStatusCode>>
+    where
+        R: FnMut(u64, u64) -> io::Result<Vec<u8>>,
+    {
+        self.headers.set_header("Accept-Ranges", "bytes");
+
+        let status = match ranges {
+            ByteRanges::Ignore => return Ok(None),
+            ByteRanges::Unsatisfiable => {
+                self.body.clear();
+                self.headers
+                    .set_header("Content-Range".to_owned(), format!("bytes */{}", len));
+                HttpStatusCode::Code416
+            }
+            ByteRanges::Satisfiable(ranges) if ranges.len() == 1 => {
+                let (first, last) = ranges[0];
+                self.body = read(first, last)?;
+                self.headers.set_header(
+                    "Content-Range".to_owned(),
+                    format!("bytes {}-{}/{}", first, last, len),
+                );
+                if let Some(content_type) = content_type {
+                    self.headers
+                        .set_header("Content-Type".to_owned(), content_type);
+                }
+                HttpStatusCode::Code206
+            }
+            ByteRanges::Satisfiable(ranges) => {
+                let boundary = base64::encode(&random_bytes::<18>()?, true);
+
+                let mut body = Vec::new();
+                for (first, last) in ranges {
+                    body.extend(format!("--{}\r\n", boundary).as_bytes());
+                    if let Some(content_type) = &content_type {
+                        body.extend(format!("Content-Type: {}\r\n", content_type).as_bytes());
+                    }
+                    body.extend(
+                        format!("Content-Range: bytes {}-{}/{}\r\n\r\n", first, last, len)
+                            .as_bytes(),
+                    );
+                    body.extend(read(first, last)?);
+                    body.extend(b"\r\n");
+                }
+                body.extend(format!("--{}--\r\n", boundary).as_bytes());
+
+                self.body = body;
+                self.headers.set_header(
+                    "Content-Type".to_owned(),
+                    format!("multipart/byteranges; boundary={}", boundary),
+                );
+                HttpStatusCode::Code206
+            }
+        };
+        self.status = status.clone();
+
+        Ok(Some(status))
+    }
+
+    // cuts an already generated 200 body down to the ranges the request asked for,
+    // honoring `If-Range` against the `ETag` and `Last-Modified` headers set so far
+    pub fn byte_ranges(&mut self, req: &Request) -> HttpStatusCode {
+        if self.status != HttpStatusCode::Code200 {
+            return self.status.clone();
+        }
+
+        let etag = self.headers.get_header("ETag").cloned();
+        let last_modified = self
+            .headers
+            .get_header("Last-Modified")
+            .and_then(|date| parse_http_date(date));
+        let ranges = req.byte_ranges(self.body.len() as u64, etag.as_deref(), last_modified);
+
+        let body = std::mem::take(&mut self.body);
+        let content_type = self.headers.get_header("Content-Type").cloned();
+        let sent = self.send_ranges(ranges, body.len() as u64, content_type, |first, last| {
+            Ok(body[first as usize..=last as usize].to_vec())
+        });
+
+        match sent {
+            Ok(Some(status)) => status,
+            _ => {
+                self.body = body;
+                self.status.clone()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut req = Request::default();
+        for (name, value) in headers {
+            req.headers.set_header(*name, *value);
+        }
+
+        req
+    }
+
+    #[test]
+    fn it_parses_ranges() {
+        use ByteRanges::*;
+
+        assert_eq!(
+            parse_ranges("bytes=0-499", 1000),
+            Satisfiable(vec![(0, 499)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=500-", 1000),
+            Satisfiable(vec![(500, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=-200", 1000),
+            Satisfiable(vec![(800, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=-2000", 1000),
+            Satisfiable(vec![(0, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=900-5000", 1000),
+            Satisfiable(vec![(900, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=500-600, 0-99, 50-150,601-700", 1000),
+            Satisfiable(vec![(0, 150), (500, 700)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=0-0, 2000-3000", 1000),
+            Satisfiable(vec![(0, 0)])
+        );
+
+        assert_eq!(parse_ranges("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=-5", 0), Unsatisfiable);
+
+        assert_eq!(parse_ranges("items=0-5", 1000), Ignore);
+        assert_eq!(parse_ranges("bytes=5-1", 1000), Ignore);
+        assert_eq!(parse_ranges("bytes=a-b", 1000), Ignore);
+        assert_eq!(parse_ranges("bytes=+1-2", 1000), Ignore);
+        assert_eq!(parse_ranges("bytes=", 1000), Ignore);
+        assert_eq!(
+            parse_ranges(&format!("bytes={}", vec!["0-1"; 33].join(",")), 1000),
+            Ignore
+        );
+    }
+
+    #[test]
+    fn it_sends_partial_content() {
+        let mut res = test_response();
+        res.text("0123456789");
+        let status = res.byte_ranges(&request(&[("Range", "bytes=2-4")]));
+        assert_eq!(status, HttpStatusCode::Code206);
+        assert_eq!(res.body, b"234");
+        assert_eq!(
+            res.headers.get_header("Content-Range").map(String::as_str),
+            Some("bytes 2-4/10")
+        );
+        assert_eq!(
+            res.headers.get_header("Accept-Ranges").map(String::as_str),
+            Some("bytes")
+        );
+
+        let mut res = test_response();
+        res.text("0123456789");
+        res.byte_ranges(&request(&[("Range", "bytes=0-1,-2")]));
+        let content_type = res.headers.get_header("Content-Type").unwrap().clone();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(res.body).unwrap(),
+            format!(
+                "--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+                 --{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+                 --{0}--\r\n",
+                boundary
+            )
+        );
+
+        let mut res = test_response();
+        res.text("0123456789");
+        assert_eq!(
+            res.byte_ranges(&request(&[("Range", "bytes=20-")])),
+            HttpStatusCode::Code416
+        );
+        assert!(res.body.is_empty());
+        assert_eq!(
+            res.headers.get_header("Content-Range").map(String::as_str),
+            Some("bytes */10")
+        );
+
+        let mut res = test_response();
+        res.text("0123456789");
+        assert_eq!(
+            res.byte_ranges(&request(&[("Range", "bytes=junk")])),
+            HttpStatusCode::Code200
+        );
+        assert_eq!(res.body, b"0123456789");
+    }
+
+    #[test]
+    fn it_honors_if_range() {
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let modified = parse_http_date(date);
+        let etag = Some("\"v1\"");
+
+        let ranges = |headers: &[(&str, &str)]| {
+            request(headers).byte_ranges(10, etag, modified) != ByteRanges::Ignore
+        };
+
+        assert!(ranges(&[("Range", "bytes=0-1")]));
+        assert!(ranges(&[("Range", "bytes=0-1"), ("If-Range", "\"v1\"")]));
+        assert!(!ranges(&[("Range", "bytes=0-1"), ("If-Range", "\"v2\"")]));
+        assert!(!ranges(&[("Range", "bytes=0-1"), ("If-Range", "W/\"v1\"")]));
+        assert!(ranges(&[("Range", "bytes=0-1"), ("If-Range", date)]));
+        assert!(!ranges(&[
+            ("Range", "bytes=0-1"),
+            ("If-Range", "Sun, 06 Nov 1994 08:49:38 GMT")
+        ]));
+
+        let head = Request {
+            method: Method::Head,
+            ..request(&[("Range", "bytes=0-1")])
+        };
+        assert_eq!(head.byte_ranges(10, etag, modified), ByteRanges::Ignore);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::types::{
+    Bucket, HeaderMethods, HttpStatusCode, Middleware, RateLimit, Request, Response,
+};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// what a client is told after asking, either way
+#[derive(Debug, PartialEq)]
+struct Decision {
+    allowed: bool,
+    remaining: u32,
+    // seconds until the next request would be allowed, or until the bucket is full again
+    retry_after: u64,
+    reset: u64,
+}
+
+impl RateLimit {
+    // allows bursts of up to `limit` requests, refilled evenly over `window`
+    pub fn new(limit: u32, window: Duration) -> RateLimit {
+        RateLimit {
+            limit: limit.max(1),
+            window,
+            max_keys: 10_000,
+            key: Box::new(|req| req.client_ip().map(|ip| ip.to_string())),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // groups requests by something other than the client ip, e.g. an api key;
+    // requests the extractor returns `None` for aren't limited
+    pub fn key<K>(mut self, key: K) -> RateLimit
+    where
+        K: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Box::new(key);
+        self
+    }
+
+    // the most clients tracked at once, the least recently seen are forgotten first
+    pub fn max_keys(mut self, max_keys: usize) -> RateLimit {
+        self.max_keys = max_keys.max(1);
+        self
+    }
+
+    fn per_second(&self) -> f64 {
+        f64::from(self.limit) / self.window.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        // a bucket that has refilled completely is the same as no bucket
+        buckets.retain(|_, bucket| now.duration_since(bucket.updated) < self.window);
+
+        if buckets.len() >= self.max_keys {
+            // drop the oldest tenth at once so this doesn't run on every new client
+            let mut updated = buckets
+                .values()
+                .map(|bucket| bucket.updated)
+                .collect::<Vec<_>>();
+            updated.sort_unstable();
+            let cutoff = updated[buckets.len() - self.max_keys * 9 / 10 - 1];
+            buckets.retain(|_, bucket| bucket.updated > cutoff);
+        }
+    }
+
+    fn take(&self, key: &str, now: Instant) -> Decision {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(key) && buckets.len() >= self.max_keys {
+            self.evict(&mut buckets, now);
+        }
+
+        let limit = f64::from(self.limit);
+        let rate = self.per_second();
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: limit,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(limit);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Decision {
+            allowed,
+            remaining: bucket.tokens as u32,
+            retry_after: ((1.0 - bucket.tokens).max(0.0) / rate).ceil() as u64,
+            reset: ((limit - bucket.tokens) / rate).ceil() as u64,
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, req: &mut Request, res: &mut Response) -> Option<HttpStatusCode> {
+        let key = (self.key)(req)?;
+        let decision = self.take(&key, Instant::now());
+
+        res.headers
+            .set_header("RateLimit-Limit".to_owned(), self.limit.to_string());
+        res.headers.set_header(
+            "RateLimit-Remaining".to_owned(),
+            decision.remaining.to_string(),
+        );
+        res.headers
+            .set_header("RateLimit-Reset".to_owned(), decision.reset.to_string());
+
+        if decision.allowed {
+            return None;
+        }
+
+        res.text("Too many requests");
+        res.headers.set_header(
+            "Retry-After".to_owned(),
+            decision.retry_after.max(1).to_string(),
+        );
+
+        Some(HttpStatusCode::Code429)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+
+    #[test]
+    fn it_refills_buckets() {
+        let limit = RateLimit::new(3, Duration::from_secs(30));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        for remaining in [2, 1, 0] {
+            let decision = limit.take("a", start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+
+        let denied = limit.take("a", at(5));
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, 5);
+        assert_eq!(denied.reset, 25);
+
+        // other clients have their own bucket
+        assert!(limit.take("b", at(5)).allowed);
+
+        // one token comes back every 10 seconds
+        assert!(limit.take("a", at(10)).allowed);
+        assert!(!limit.take("a", at(11)).allowed);
+        assert_eq!(limit.take("a", at(100)).remaining, 2);
+    }
+
+    #[test]
+    fn it_evicts_old_clients() {
+        let limit = RateLimit::new(1, Duration::from_secs(60)).max_keys(10);
+        let start = Instant::now();
+
+        for i in 0..10 {
+            limit.take(&i.to_string(), start + Duration::from_secs(i));
+        }
+        assert_eq!(limit.buckets.lock().unwrap().len(), 10);
+
+        // buckets that have refilled are dropped first
+        limit.take("new", start + Duration::from_secs(65));
+        assert_eq!(limit.buckets.lock().unwrap().len(), 5);
+
+        for i in 0..10 {
+            limit.take(&format!("more{}", i), start + Duration::from_secs(66));
+        }
+        let buckets = limit.buckets.lock().unwrap();
+        assert!(buckets.len() <= 10);
+        assert!(buckets.contains_key("more9"));
+        assert!(!buckets.contains_key("5"));
+    }
+
+    #[test]
+    fn it_answers_too_many_requests() {
+        let limit = RateLimit::new(1, Duration::from_secs(60));
+        let mut req = Request {
+            client_ip: "203.0.113.7".parse().ok(),
+            ..Default::default()
+        };
+
+        let mut res = test_response();
+        assert_eq!(limit.before(&mut req, &mut res), None);
+        assert_eq!(
+            res.headers
+                .get_header("RateLimit-Remaining")
+                .map(String::as_str),
+            Some("0")
+        );
+
+        let mut res = test_response();
+        assert_eq!(
+            limit.before(&mut req, &mut res),
+            Some(HttpStatusCode::Code429)
+        );
+        assert_eq!(
+            res.headers.get_header("Retry-After").map(String::as_str),
+            Some("60")
+        );
+        assert_eq!(
+            res.headers
+                .get_header("RateLimit-Limit")
+                .map(String::as_str),
+            Some("1")
+        );
+
+        // no key, no limit
+        let keyed = RateLimit::new(1, Duration::from_secs(60))
+            .key(|req| req.headers.get_header("X-Api-Key").cloned());
+        let mut anonymous = Request::default();
+        for _ in 0..3 {
+            assert_eq!(keyed.before(&mut anonymous, &mut test_response()), None);
+        }
+    }
+}
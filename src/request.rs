@@ -0,0 +1,219 @@
+use crate::form::parse_urlencoded;
+use crate::multipart::header_params;
+use crate::types::{Form, HeaderMethods, HttpParseError, JsonValue, Multipart, Request, Spooled};
+
+use std::borrow::Cow;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+
+impl Request {
+    pub(crate) fn set_body(&mut self, body: Spooled) {
+        match body {
+            Spooled::Memory(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => {
+                    self.body = Some(text);
+                    self.raw_body = None;
+                }
+                Err(err) => {
+                    self.body = Some(String::from_utf8_lossy(err.as_bytes()).into_owned());
+                    self.raw_body = Some(Spooled::Memory(err.into_bytes()));
+                }
+            },
+            spooled => {
+                self.body = None;
+                self.raw_body = Some(spooled);
+            }
+        }
+    }
+
+    // the other end of the connection, which may be a proxy rather than the client
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    // true when the request came in over tls
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    // the address the connection was accepted on
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    // the exact bytes that were sent, unless the body was large enough to be moved to disk
+    pub fn body_bytes(&self) -> Option<&[u8]> {
+        match &self.raw_body {
+            Some(Spooled::Memory(bytes)) => Some(bytes),
+            Some(Spooled::File(_)) => None,
+            None => Some(self.body.as_deref().unwrap_or("").as_bytes()),
+        }
+    }
+
+    pub fn body_reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.raw_body {
+            Some(spooled) => spooled.reader(),
+            None => Ok(Box::new(self.body.as_deref().unwrap_or("").as_bytes())),
+        }
+    }
+
+    fn body_text(&self) -> Result<Cow<'_, str>, HttpParseError> {
+        match (&self.body, &self.raw_body) {
+            (_, Some(Spooled::File(_))) => {
+                let mut text = String::new();
+                self.body_reader()
+                    .and_then(|mut reader| reader.read_to_string(&mut text))
+                    .map_err(|err| HttpParseError::Other(format!("{}", err)))?;
+
+                Ok(Cow::Owned(text))
+            }
+            (Some(text), _) => Ok(Cow::Borrowed(text)),
+            (None, _) => Ok(Cow::Borrowed("")),
+        }
+    }
+
+    // the lowercased `type/subtype` of the body, without any parameters
+    pub fn media_type(&self) -> Option<String> {
+        self.headers
+            .get_header("Content-Type")
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|media_type| media_type.trim().to_ascii_lowercase())
+            .filter(|media_type| !media_type.is_empty())
+    }
+
+    fn expect_media_type<F>(&self, accepts: F) -> Result<(), HttpParseError>
+    where
+        F: Fn(&str) -> bool,
+    {
+        match self.media_type() {
+            Some(media_type) if accepts(&media_type) => Ok(()),
+            media_type => Err(HttpParseError::UnsupportedMediaType(media_type)),
+        }
+    }
+
+    pub fn json(&self) -> Result<JsonValue, HttpParseError> {
+        self.expect_media_type(|media_type| {
+            media_type == "application/json"
+                || (media_type.starts_with("application/") && media_type.ends_with("+json"))
+        })?;
+
+        JsonValue::parse_raw(&self.body_text()?).map_err(HttpParseError::InvalidJson)
+    }
+
+    pub fn form(&self) -> Result<Form, HttpParseError> {
+        self.expect_media_type(|media_type| media_type == "application/x-www-form-urlencoded")?;
+
+        Ok(Form(parse_urlencoded(&self.body_text()?)))
+    }
+
+    pub fn multipart(&self) -> Result<Multipart<'_>, HttpParseError> {
+        self.expect_media_type(|media_type| media_type == "multipart/form-data")?;
+
+        let boundary = self
+            .headers
+            .get_header("Content-Type")
+            .map(|content_type| header_params(content_type).1)
+            .and_then(|params| params.into_iter().find(|(key, _)| key == "boundary"))
+            .map(|(_, boundary)| boundary)
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+            .ok_or_else(|| HttpParseError::InvalidMultipart("Missing boundary".to_owned()))?;
+
+        let reader = self
+            .body_reader()
+            .map_err(|err| HttpParseError::Other(format!("{}", err)))?;
+
+        Ok(Multipart::new(reader, &boundary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::json_object;
+    use crate::types::JsonError;
+
+    fn request(content_type: Option<&str>, body: &str) -> Request {
+        let mut req = Request {
+            body: Some(body.to_owned()),
+            ..Default::default()
+        };
+        if let Some(content_type) = content_type {
+            req.headers.set_header("Content-Type", content_type);
+        }
+
+        req
+    }
+
+    #[test]
+    fn it_reads_json_bodies() {
+        assert_eq!(
+            request(Some("application/json; charset=utf-8"), r#"{"a": [true]}"#).json(),
+            Ok(json_object! {"a" => vec![true]})
+        );
+        assert_eq!(
+            request(Some("Application/Problem+JSON"), "1").json(),
+            Ok(JsonValue::Number(1.0))
+        );
+    }
+
+    #[test]
+    fn it_rejects_bad_json_bodies() {
+        let err = request(Some("text/plain"), "{}").json().unwrap_err();
+        assert_eq!(
+            err,
+            HttpParseError::UnsupportedMediaType(Some("text/plain".into()))
+        );
+        assert_eq!(err.status(), crate::HttpStatusCode::Code415);
+
+        assert_eq!(
+            request(None, "{}").json(),
+            Err(HttpParseError::UnsupportedMediaType(None))
+        );
+
+        let err = request(Some("application/json"), "{").json().unwrap_err();
+        assert_eq!(err, HttpParseError::InvalidJson(JsonError::UnexpectedEof));
+        assert_eq!(err.status(), crate::HttpStatusCode::Code400);
+    }
+
+    #[test]
+    fn it_reads_form_bodies() {
+        let form = request(
+            Some("application/x-www-form-urlencoded"),
+            "name=Rebar+Server&lang=rust&lang=c%2B%2B",
+        )
+        .form()
+        .unwrap();
+        assert_eq!(form.get("name"), Some("Rebar Server"));
+        assert_eq!(form.get_all("lang"), vec!["rust", "c++"]);
+
+        assert_eq!(
+            request(Some("application/json"), "a=1").form(),
+            Err(HttpParseError::UnsupportedMediaType(Some(
+                "application/json".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn it_reads_multipart_bodies() {
+        let req = request(
+            Some("multipart/form-data; boundary=\"a b\""),
+            "--a b\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\n1\r\n--a b--\r\n",
+        );
+        let parts = req.multipart().unwrap().collect::<Vec<_>>();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].as_ref().unwrap().text(), Some("1"));
+
+        assert_eq!(
+            request(Some("multipart/form-data"), "").multipart().err(),
+            Some(HttpParseError::InvalidMultipart("Missing boundary".into()))
+        );
+        assert_eq!(
+            request(Some("text/plain"), "").multipart().err(),
+            Some(HttpParseError::UnsupportedMediaType(Some(
+                "text/plain".into()
+            )))
+        );
+    }
+}
@@ -1,29 +1,47 @@
-use crate::types::{HttpResponseError, HttpStatusCode, Response};
+use crate::mime::content_type;
+use crate::types::{HeaderMethods, HttpResponseError, HttpStatusCode, Response};
 
-use std::io::Write;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::fs::read;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
 
 fn to_http_response_error(err: std::io::Error) -> HttpResponseError {
     HttpResponseError::Other(format! {"{:?}", err})
 }
 
 impl Response {
+    fn status_line(&self) -> String {
+        let reason = match &self.reason {
+            Some((status, reason)) if *status == self.status => reason,
+            _ => self.status.reason(),
+        };
+
+        format!(
+            "{} {} {}\r\n",
+            self.http_version,
+            self.status.as_u16(),
+            reason
+        )
+    }
+
     fn write_head(&mut self) -> Result<(), HttpResponseError> {
-        let head = format!(
-            "{} {}\r\n{}\r\n",
-            self.http_version, self.status, self.headers
-        );
+        let mut head = format!("{}{}", self.status_line(), self.headers);
+        for cookie in &self.cookies {
+            head.push_str(&format!("Set-Cookie: {}\r\n", cookie));
+        }
+        head.push_str("\r\n");
 
         self.stream
-            .write(head.as_bytes())
-            .and(Ok(()))
-            .map_err(|err| to_http_response_error(err))
+            .write_all(head.as_bytes())
+            .map_err(to_http_response_error)
     }
 
     fn write_body(&mut self) -> Result<(), HttpResponseError> {
         self.stream
-            .write(&self.body)
-            .and(Ok(()))
-            .map_err(|err| to_http_response_error(err))
+            .write_all(&self.body)
+            .map_err(to_http_response_error)
     }
 
     pub(crate) fn send(mut self) -> Result<HttpStatusCode, HttpResponseError> {
@@ -36,3 +54,270 @@ impl Response {
         }
     }
 }
+
+impl Response {
+    fn set_body<B>(&mut self, content_type: &str, body: B, status: HttpStatusCode) -> HttpStatusCode
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.body = body.into();
+        self.headers.set_header("Content-Type", content_type);
+        self.status = status.clone();
+
+        status
+    }
+
+    pub fn text<S>(&mut self, body: S) -> HttpStatusCode
+    where
+        S: Into<String>,
+    {
+        self.set_body(
+            "text/plain; charset=utf-8",
+            body.into(),
+            HttpStatusCode::Code200,
+        )
+    }
+
+    pub fn html<S>(&mut self, body: S) -> HttpStatusCode
+    where
+        S: Into<String>,
+    {
+        self.set_body(
+            "text/html; charset=utf-8",
+            body.into(),
+            HttpStatusCode::Code200,
+        )
+    }
+
+    // anything displayable works here, so an already serialized string can be passed as is
+    pub fn json<T>(&mut self, body: T) -> HttpStatusCode
+    where
+        T: Display,
+    {
+        self.set_body(
+            "application/json",
+            body.to_string(),
+            HttpStatusCode::Code200,
+        )
+    }
+
+    // sends `reason` instead of the standard phrase whenever the response ends up with
+    // `status`; it goes straight into the status line, so control characters are refused
+    pub fn set_reason<S>(
+        &mut self,
+        status: HttpStatusCode,
+        reason: S,
+    ) -> Result<(), HttpResponseError>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let reason = reason.into();
+        if reason.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(HttpResponseError::Other(format!(
+                "Control character in reason phrase {:?}",
+                reason
+            )));
+        }
+        self.reason = Some((status, reason));
+
+        Ok(())
+    }
+
+    // adds a request header name to `Vary`, keeping the ones already listed
+    pub fn add_vary(&mut self, name: &str) -> &mut Self {
+        let vary = match self.headers.get_header("Vary") {
+            Some(vary) if vary.split(',').any(|v| v.trim().eq_ignore_ascii_case(name)) => {
+                return self
+            }
+            Some(vary) => format!("{}, {}", vary, name),
+            None => name.to_owned(),
+        };
+        self.headers.set_header("Vary".to_owned(), vary);
+
+        self
+    }
+
+    pub fn redirect<S>(&mut self, location: S, permanent: bool) -> HttpStatusCode
+    where
+        S: Into<String>,
+    {
+        self.body.clear();
+        self.headers
+            .remove_header("Content-Type")
+            .set_header("Location".to_owned(), location.into());
+        self.status = if permanent {
+            HttpStatusCode::Code308
+        } else {
+            HttpStatusCode::Code307
+        };
+
+        self.status.clone()
+    }
+
+    pub fn no_content(&mut self) -> HttpStatusCode {
+        self.body.clear();
+        self.headers.remove_header("Content-Type");
+        self.status = HttpStatusCode::Code204;
+
+        self.status.clone()
+    }
+
+    pub fn file<P>(&mut self, path: P) -> HttpStatusCode
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        match read(path) {
+            Ok(contents) => self.set_body(content_type(path), contents, HttpStatusCode::Code200),
+            Err(err) => {
+                self.status = match err.kind() {
+                    ErrorKind::NotFound => HttpStatusCode::Code404,
+                    ErrorKind::PermissionDenied => HttpStatusCode::Code403,
+                    // reading a directory fails with an os specific error so check for it explicitly
+                    _ if path.is_dir() => HttpStatusCode::Code404,
+                    _ => HttpStatusCode::Code500,
+                };
+
+                self.status.clone()
+            }
+        }
+    }
+}
+
+// a response for tests that only look at what was done to it, it's never sent
+#[cfg(test)]
+pub(crate) fn test_response() -> Response {
+    use crate::types::{Duplex, Headers, HttpVersion};
+
+    use std::collections::HashMap;
+    use std::io::{empty, sink};
+
+    Response {
+        stream: Box::new(Duplex::new(empty(), sink())),
+
+        headers: Headers(HashMap::new()),
+        http_version: HttpVersion::Http1_1,
+        status: HttpStatusCode::Code200,
+        body: vec![],
+
+        cookies: vec![],
+
+        reason: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_sends_custom_reasons() {
+        let mut res = test_response();
+        assert_eq!(res.status_line(), "HTTP/1.1 200 OK\r\n");
+
+        res.set_reason(HttpStatusCode::new(499).unwrap(), "Client Closed Request")
+            .unwrap();
+        assert_eq!(res.status_line(), "HTTP/1.1 200 OK\r\n");
+        res.status = HttpStatusCode::new(499).unwrap();
+        assert_eq!(res.status_line(), "HTTP/1.1 499 Client Closed Request\r\n");
+
+        res.status = HttpStatusCode::new(599).unwrap();
+        assert_eq!(res.status_line(), "HTTP/1.1 599 \r\n");
+
+        let err = res.set_reason(HttpStatusCode::Code200, "OK\r\nSet-Cookie: a=b");
+        assert!(err.is_err());
+        assert!(res.set_reason(HttpStatusCode::Code200, "OK\n").is_err());
+        assert!(res
+            .set_reason(HttpStatusCode::Code200, "Fine\tthanks")
+            .is_ok());
+    }
+
+    #[test]
+    fn it_builds_bodies() {
+        let mut res = test_response();
+        assert_eq!(res.text("hello"), HttpStatusCode::Code200);
+        assert_eq!(res.body, b"hello");
+        assert_eq!(
+            res.headers.get_header("content-type"),
+            Some(&"text/plain; charset=utf-8".to_owned())
+        );
+
+        assert_eq!(res.html("<p>hi</p>"), HttpStatusCode::Code200);
+        assert_eq!(
+            res.headers.get_header("content-type"),
+            Some(&"text/html; charset=utf-8".to_owned())
+        );
+
+        assert_eq!(res.json(r#"{"ok":true}"#), HttpStatusCode::Code200);
+        assert_eq!(res.body, br#"{"ok":true}"#);
+        assert_eq!(
+            res.headers.get_header("content-type"),
+            Some(&"application/json".to_owned())
+        );
+    }
+
+    #[test]
+    fn it_builds_empty_responses() {
+        let mut res = test_response();
+        res.text("gone soon");
+
+        assert_eq!(res.redirect("/elsewhere", true), HttpStatusCode::Code308);
+        assert_eq!(res.redirect("/elsewhere", false), HttpStatusCode::Code307);
+        assert_eq!(
+            res.headers.get_header("location"),
+            Some(&"/elsewhere".to_owned())
+        );
+        assert_eq!(res.headers.get_header("content-type"), None);
+        assert!(res.body.is_empty());
+
+        assert_eq!(res.no_content(), HttpStatusCode::Code204);
+        assert_eq!(res.status, HttpStatusCode::Code204);
+    }
+
+    #[test]
+    fn it_builds_file_responses() {
+        let mut res = test_response();
+
+        assert_eq!(res.file("./static/index.html"), HttpStatusCode::Code200);
+        assert!(res.body.starts_with(b"<!DOCTYPE html>"));
+        assert_eq!(
+            res.headers.get_header("content-type"),
+            Some(&"text/html; charset=utf-8".to_owned())
+        );
+
+        assert_eq!(res.file("./static/missing.html"), HttpStatusCode::Code404);
+        assert_eq!(res.file("./static"), HttpStatusCode::Code404);
+    }
+
+    #[test]
+    fn it_merges_vary() {
+        let mut res = test_response();
+        res.add_vary("Accept-Encoding")
+            .add_vary("Origin")
+            .add_vary("accept-encoding");
+        assert_eq!(
+            res.headers.get_header("vary"),
+            Some(&"Accept-Encoding, Origin".to_owned())
+        );
+    }
+
+    #[test]
+    fn it_keeps_one_cookie_per_name_and_path() {
+        use crate::types::Cookie;
+
+        let mut res = test_response();
+        res.set_cookie(Cookie::new("sid", "1").path("/"))
+            .set_cookie(Cookie::new("sid", "2").path("/"))
+            .set_cookie(Cookie::new("sid", "3").path("/admin"));
+        assert_eq!(res.cookies().len(), 2);
+        assert_eq!(res.cookies()[0].value, "2");
+
+        res.remove_cookie(Cookie::new("sid", "").path("/admin"));
+        assert_eq!(res.cookies().len(), 2);
+        assert_eq!(
+            res.cookies()[1].to_string(),
+            "sid=; Path=/admin; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+}
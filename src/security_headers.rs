@@ -0,0 +1,198 @@
+use crate::base64;
+use crate::crypto::random_bytes;
+use crate::types::{HeaderMethods, HttpStatusCode, Middleware, Request, Response, SecurityHeaders};
+
+use std::time::Duration;
+
+// replaced with a fresh value on every request so inline scripts can be allowed one by one
+const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+impl SecurityHeaders {
+    // a strict starting point, loosen what the site needs with the setters below
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders { headers: vec![] }
+            .strict_transport_security(Duration::from_secs(365 * 24 * 60 * 60), true, false)
+            .content_security_policy(
+                "default-src 'self'; base-uri 'self'; object-src 'none'; frame-ancestors 'none'",
+            )
+            .set("X-Content-Type-Options", "nosniff")
+            .frame_options("DENY")
+            .referrer_policy("strict-origin-when-cross-origin")
+    }
+
+    pub fn set(mut self, name: &str, value: &str) -> SecurityHeaders {
+        self.headers
+            .retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    // stops one of the defaults from being sent
+    pub fn remove(mut self, name: &str) -> SecurityHeaders {
+        self.headers
+            .retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+        self
+    }
+
+    pub fn strict_transport_security(
+        self,
+        max_age: Duration,
+        include_subdomains: bool,
+        preload: bool,
+    ) -> SecurityHeaders {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if preload {
+            value.push_str("; preload");
+        }
+
+        self.set("Strict-Transport-Security", &value)
+    }
+
+    // `{nonce}` anywhere in the policy is replaced with the request's nonce,
+    // e.g. `script-src 'self' 'nonce-{nonce}'`
+    pub fn content_security_policy(self, policy: &str) -> SecurityHeaders {
+        self.set("Content-Security-Policy", policy)
+    }
+
+    pub fn frame_options(self, value: &str) -> SecurityHeaders {
+        self.set("X-Frame-Options", value)
+    }
+
+    pub fn referrer_policy(self, policy: &str) -> SecurityHeaders {
+        self.set("Referrer-Policy", policy)
+    }
+
+    pub fn permissions_policy(self, policy: &str) -> SecurityHeaders {
+        self.set("Permissions-Policy", policy)
+    }
+
+    fn uses_nonce(&self) -> bool {
+        self.headers
+            .iter()
+            .any(|(_, value)| value.contains(NONCE_PLACEHOLDER))
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::new()
+    }
+}
+
+impl Request {
+    // `None` unless `SecurityHeaders` is installed with a policy that asks for one
+    pub fn csp_nonce(&self) -> Option<&str> {
+        self.csp_nonce.as_deref()
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn before(&self, req: &mut Request, _res: &mut Response) -> Option<HttpStatusCode> {
+        if self.uses_nonce() {
+            match random_bytes::<16>() {
+                Ok(nonce) => req.csp_nonce = Some(base64::encode(&nonce, false)),
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    return Some(HttpStatusCode::Code500);
+                }
+            }
+        }
+
+        None
+    }
+
+    // handlers that set one of these themselves keep their own value
+    fn after(&self, req: &Request, res: &mut Response) {
+        for (name, value) in &self.headers {
+            if res.headers.get_header(name.as_str()).is_some() {
+                continue;
+            }
+
+            let value = match &req.csp_nonce {
+                Some(nonce) => value.replace(NONCE_PLACEHOLDER, nonce),
+                None => value.clone(),
+            };
+            res.headers.set_header(name.clone(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+    use crate::types::Template;
+
+    use std::collections::HashMap;
+
+    fn header<'a>(res: &'a Response, name: &str) -> Option<&'a str> {
+        res.headers.get_header(name).map(String::as_str)
+    }
+
+    #[test]
+    fn it_sets_security_headers() {
+        let headers = SecurityHeaders::new()
+            .remove("x-frame-options")
+            .permissions_policy("camera=(), geolocation=()");
+
+        let mut req = Request::default();
+        let mut res = test_response();
+        res.headers.set_header("Referrer-Policy", "no-referrer");
+        assert_eq!(headers.before(&mut req, &mut res), None);
+        headers.after(&req, &mut res);
+
+        assert_eq!(req.csp_nonce(), None);
+        assert_eq!(
+            header(&res, "Strict-Transport-Security"),
+            Some("max-age=31536000; includeSubDomains")
+        );
+        assert_eq!(
+            header(&res, "Content-Security-Policy"),
+            Some("default-src 'self'; base-uri 'self'; object-src 'none'; frame-ancestors 'none'")
+        );
+        assert_eq!(header(&res, "X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(header(&res, "X-Frame-Options"), None);
+        assert_eq!(header(&res, "Referrer-Policy"), Some("no-referrer"));
+        assert_eq!(
+            header(&res, "Permissions-Policy"),
+            Some("camera=(), geolocation=()")
+        );
+    }
+
+    #[test]
+    fn it_issues_nonces() {
+        let headers =
+            SecurityHeaders::new().content_security_policy("script-src 'self' 'nonce-{nonce}'");
+        let template = Template::create_from_string("<script nonce=\"{{ csp_nonce }}\">").unwrap();
+
+        let mut nonces = vec![];
+        for _ in 0..2 {
+            let mut req = Request::default();
+            let mut res = test_response();
+            headers.before(&mut req, &mut res);
+            headers.after(&req, &mut res);
+
+            let nonce = req.csp_nonce().unwrap().to_owned();
+            assert_eq!(nonce.len(), 24);
+            assert_eq!(
+                header(&res, "Content-Security-Policy"),
+                Some(format!("script-src 'self' 'nonce-{}'", nonce).as_str())
+            );
+            assert_eq!(
+                template.soak_request(&req, HashMap::new()),
+                Ok(format!("<script nonce=\"{}\">", nonce))
+            );
+            nonces.push(nonce);
+        }
+        assert_ne!(nonces[0], nonces[1]);
+
+        assert_eq!(
+            template.soak_request(&Request::default(), HashMap::new()),
+            Ok("<script nonce=\"\">".to_owned())
+        );
+    }
+}
@@ -1,23 +1,125 @@
 use crate::parse::parse;
-use crate::types::{HeaderMethods, Headers, HttpStatusCode, LogError, Request, Response, Server};
+use crate::proxy::client_ip;
+use crate::types::{
+    Connection, HeaderMethods, Headers, HttpParseError, HttpStatusCode, Listener, LogError,
+    Middleware, ParseConfig, Request, Response, Server, ShutdownHandle, Transport, TrustedProxy,
+};
 
 use std::collections::HashMap;
-use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::Duration;
 
-fn create_response(stream: TcpStream, req: &Request) -> Response {
+fn create_response(stream: Box<dyn Transport>, req: &Request) -> Response {
     let mut headers = Headers(HashMap::new());
 
     headers.set_header("Content-Type", "text/html; charset=utf-8");
 
     Response {
-        stream: stream,
+        stream,
 
         headers,
         status: HttpStatusCode::Code200,
         http_version: req.http_version.clone(),
         body: vec![],
+
+        cookies: vec![],
+
+        reason: None,
+    }
+}
+
+fn serve<F>(
+    stream: Box<dyn Transport>,
+    handler: &Mutex<Option<F>>,
+    middleware: &[Arc<dyn Middleware>],
+    config: &ParseConfig,
+) where
+    F: Fn(&Request, &mut Response) -> Result<HttpStatusCode, Box<dyn std::error::Error>>,
+{
+    answer(stream, handler, middleware, config)
+        .send()
+        .log_error();
+}
+
+// reads a request from `stream` and runs it through the middleware and handler, the
+// response is left for the caller to send
+pub(crate) fn answer<F>(
+    mut stream: Box<dyn Transport>,
+    handler: &Mutex<Option<F>>,
+    middleware: &[Arc<dyn Middleware>],
+    config: &ParseConfig,
+) -> Response
+where
+    F: Fn(&Request, &mut Response) -> Result<HttpStatusCode, Box<dyn std::error::Error>>,
+{
+    match parse(&mut stream, config) {
+        Ok(mut req) => {
+            req.remote_addr = stream.peer_addr();
+            req.local_addr = stream.local_addr();
+            req.client_ip = client_ip(
+                &req,
+                &config.trusted_proxies,
+                config.trust_addressless_peers,
+            );
+            req.secure = stream.is_secure();
+            let mut res = create_response(stream, &req);
+
+            let handler = handler.lock().unwrap();
+            dispatch(handler.as_ref(), middleware, &mut req, &mut res);
+
+            res
+        }
+        Err(err) => {
+            let mut res = create_response(stream, &Default::default());
+            res.status = err.status();
+            res
+        }
+    }
+}
+
+pub(crate) fn dispatch<F>(
+    handler: Option<&F>,
+    middleware: &[Arc<dyn Middleware>],
+    req: &mut Request,
+    res: &mut Response,
+) where
+    F: Fn(&Request, &mut Response) -> Result<HttpStatusCode, Box<dyn std::error::Error>>,
+{
+    let mut ran = 0;
+    let mut answered = None;
+    for layer in middleware {
+        ran += 1;
+        if let Some(status) = layer.before(req, res) {
+            answered = Some(status);
+            break;
+        }
+    }
+
+    match answered {
+        Some(status) => res.status = status,
+        None => {
+            if let Some(handler) = handler {
+                res.status = match handler(req, res) {
+                    Ok(status) => status,
+                    // body errors bubbled up with `?` still map to their own status
+                    Err(err) => match err.downcast_ref::<HttpParseError>() {
+                        Some(err) => err.status(),
+                        None => HttpStatusCode::Code500,
+                    },
+                };
+            }
+        }
+    }
+
+    // only layers that saw the request get to see the response
+    for layer in middleware[..ran].iter().rev() {
+        layer.after(req, res);
     }
 }
 
@@ -27,57 +129,290 @@ where
         + Send
         + 'static,
 {
-    pub fn new(on: &str) -> Server<F> {
+    fn with_listener(listener: Option<Listener>) -> Server<F> {
         Server {
-            listener: TcpListener::bind(on).unwrap(),
+            shutdown: ShutdownHandle {
+                stopping: Arc::new(AtomicBool::new(false)),
+                wake: listener.as_ref().and_then(Listener::listen_addr),
+            },
+            listener,
+            pending: None,
             handler: Arc::new(Mutex::new(None)),
+            middleware: vec![],
+            config: Default::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            shutdown_timeout: Duration::from_secs(30),
+            in_flight: Arc::new((Mutex::new(0), Condvar::new())),
         }
     }
 
+    pub fn new(on: &str) -> Server<F> {
+        Server::with_listener(Some(Listener::Tcp(TcpListener::bind(on).unwrap())))
+    }
+
+    // a server that never touches the network, for answering requests through
+    // `serve_transport` or `test`; `listen` panics on it
+    pub fn without_listener() -> Server<F> {
+        Server::with_listener(None)
+    }
+
+    // listens on a unix domain socket at `path` instead, e.g. behind a reverse proxy on
+    // the same host; `mode` is applied to the socket file, like `0o660`
+    #[cfg(unix)]
+    pub fn new_unix<P>(path: P, mode: u32) -> std::io::Result<Server<F>>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        Ok(Server::with_listener(Some(Listener::bind_unix(
+            path.as_ref(),
+            mode,
+        )?)))
+    }
+
+    // adopts a tcp or unix socket that is already listening, e.g. one inherited from the
+    // process that started this one
+    #[cfg(unix)]
+    pub fn from_fd(fd: OwnedFd) -> std::io::Result<Server<F>> {
+        let (listener, pending) = Listener::from_fd(fd)?;
+        let mut server = Server::with_listener(Some(listener));
+        server.pending = pending;
+        Ok(server)
+    }
+
+    // adopts the socket handed over through `LISTEN_FDS` and `LISTEN_PID`, like systemd
+    // does for socket activated services; `NotFound` if there isn't one and `InvalidInput`
+    // if there are several, which `from_fd` can take one at a time. the variables aren't
+    // cleared, a server that starts other processes should remove them from their environment
+    #[cfg(unix)]
+    pub fn from_listen_fds() -> std::io::Result<Server<F>> {
+        let (listener, pending) = Listener::from_listen_fds()?;
+        let mut server = Server::with_listener(Some(listener));
+        server.pending = pending;
+        Ok(server)
+    }
+
+    pub fn set_max_body_size(&mut self, bytes: u64) {
+        self.config.max_body_size = bytes;
+    }
+
+    // bodies larger than this, 1 MiB by default, are written to a temp file instead of being
+    // kept in memory; `req.body` is `None` for those and `req.body_reader` reads them
+    pub fn set_body_memory_limit(&mut self, bytes: usize) {
+        self.config.body_memory_limit = bytes;
+    }
+
+    // gzip and deflate request bodies are decoded before the handler sees them,
+    // anything else with a `Content-Encoding` is turned away with a 415
+    pub fn set_decompress_bodies(&mut self, enabled: bool) {
+        self.config.decompress_bodies = enabled;
+    }
+
+    pub fn set_max_decompressed_size(&mut self, bytes: u64) {
+        self.config.max_decompressed_size = bytes;
+    }
+
+    pub fn set_max_head_size(&mut self, bytes: usize) {
+        self.config.max_head_size = bytes;
+    }
+
+    // requests from these addresses or cidr blocks, e.g. `10.0.0.0/8`, may name the
+    // real client in `Forwarded` or `X-Forwarded-For`, see `Request::client_ip`
+    pub fn set_trusted_proxies(&mut self, proxies: &[&str]) -> std::io::Result<()> {
+        self.config.trusted_proxies = proxies
+            .iter()
+            .map(|proxy| {
+                TrustedProxy::parse(proxy).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid proxy address `{}`", proxy),
+                    )
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(())
+    }
+
+    // peers without an address, e.g. a reverse proxy on a unix socket, may name the real
+    // client too; off by default since anyone who can connect could then pick their ip
+    pub fn set_trust_addressless_peers(&mut self, trust: bool) {
+        self.config.trust_addressless_peers = trust;
+    }
+
+    pub fn add_middleware<M>(&mut self, middleware: M)
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+    }
+
     pub fn on_all(&mut self, handler: F) {
         self.handler = Arc::new(Mutex::new(Some(handler)));
     }
 
-    fn handle_connection(&self, mut stream: TcpStream) {
+    // how long `listen` waits for requests that are still being answered after a shutdown
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    fn handle_connection(&self, stream: Connection) {
         let handler = self.handler.clone();
-        thread::spawn(move || match parse(&mut stream) {
-            Ok(req) => {
-                let mut res = create_response(stream, &req);
-
-                let handler = handler.lock().unwrap();
-                if let Some(handler) = handler.as_ref() {
-                    if let Ok(status) = handler(&req, &mut res) {
-                        res.status = status;
-                    } else {
-                        res.status = HttpStatusCode::Code500;
-                    }
-                }
+        let middleware = self.middleware.clone();
+        let config = self.config.clone();
+        #[cfg(feature = "tls")]
+        let tls = self.tls.clone();
+        let in_flight = InFlight::new(self.in_flight.clone());
 
-                res.send().log_error();
-            }
-            Err(_) => {
-                let mut res = create_response(stream, &Default::default());
-                res.status = HttpStatusCode::Code400;
-                res.send().log_error();
-            }
+        thread::spawn(move || {
+            let _in_flight = in_flight;
+
+            // the handshake happens here so a slow client can't hold up the accept loop
+            #[cfg(feature = "tls")]
+            let stream = match tls {
+                Some(acceptor) => match stream.into_tls_transport(&acceptor) {
+                    Ok(stream) => stream,
+                    Err(err) => return println!("Error: {:?}", err),
+                },
+                None => stream.into_transport(),
+            };
+            #[cfg(not(feature = "tls"))]
+            let stream = stream.into_transport();
+
+            serve(stream, &handler, &middleware, &config);
         });
     }
 
+    // answers a single request read from `stream` on the current thread and hands the
+    // stream back afterwards, e.g. a `Duplex` to look at what was written to it
+    pub fn serve_transport<T>(&self, stream: T) -> T
+    where
+        T: Transport + 'static,
+    {
+        let shared = Arc::new(Mutex::new(stream));
+        serve(
+            Box::new(Shared(shared.clone())),
+            &self.handler,
+            &self.middleware,
+            &self.config,
+        );
+
+        // the response was sent and dropped along with the other handle
+        match Arc::try_unwrap(shared) {
+            Ok(stream) => stream.into_inner().unwrap_or_else(|err| err.into_inner()),
+            Err(_) => unreachable!("the response outlived `serve`"),
+        }
+    }
+
     pub fn listen_once(&mut self) {
-        match self.listener.accept() {
-            Ok((stream, _)) => self.handle_connection(stream),
+        let listener = self
+            .listener
+            .as_ref()
+            .expect("The server was built without a listener");
+        let accepted = match self.pending.take() {
+            Some(connection) => Ok(connection),
+            None => listener.accept(),
+        };
+        match accepted {
+            // once shutting down, whatever woke the accept up is dropped unanswered
+            Ok(_) if self.shutdown.is_stopping() => {}
+            Ok(stream) => self.handle_connection(stream),
             Err(err) => println!("Error: {:?}", err),
         }
     }
 
+    // runs until `ShutdownHandle::shutdown` is called, then returns once the requests
+    // already accepted are answered or the shutdown timeout runs out
     pub fn listen(&mut self) {
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(stream) => self.handle_connection(stream),
-                Err(err) => println!("Error: {:?}", err),
-            }
+        while !self.shutdown.is_stopping() {
+            self.listen_once();
+        }
+
+        let (count, drained) = &*self.in_flight;
+        let count = count.lock().unwrap();
+        let _ = drained
+            .wait_timeout_while(count, self.shutdown_timeout, |count| *count > 0)
+            .unwrap();
+    }
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(wake) = &self.wake {
+            wake.poke().log_error();
         }
     }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+// lets `serve` own a transport that is still wanted back afterwards
+struct Shared<T>(Arc<Mutex<T>>);
+
+impl<T> Read for Shared<T>
+where
+    T: Transport,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl<T> Write for Shared<T>
+where
+    T: Transport,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+impl<T> Transport for Shared<T>
+where
+    T: Transport,
+{
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.0.lock().unwrap().peer_addr()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.0.lock().unwrap().local_addr()
+    }
+
+    fn is_secure(&self) -> bool {
+        self.0.lock().unwrap().is_secure()
+    }
+}
+
+// counts a connection as in flight for as long as it's alive, panics included
+struct InFlight(Arc<(Mutex<usize>, Condvar)>);
+
+impl InFlight {
+    fn new(in_flight: Arc<(Mutex<usize>, Condvar)>) -> InFlight {
+        *in_flight.0.lock().unwrap() += 1;
+        InFlight(in_flight)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let (count, drained) = &*self.0;
+        // a handler that panicked while holding the lock doesn't matter here
+        let mut count = count.lock().unwrap_or_else(|err| err.into_inner());
+        *count -= 1;
+        drained.notify_all();
+    }
 }
 
 #[cfg(test)]
@@ -165,4 +500,182 @@ mod tests {
 
         handle.join().unwrap();
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_listens_on_unix_sockets() {
+        use std::io::{Read, Write};
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let path = std::env::temp_dir().join(format!("rebar-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // what a crashed server leaves behind
+        drop(UnixListener::bind(&path).unwrap());
+
+        let mut server = Server::new_unix(&path, 0o600).unwrap();
+        // only the proxy in front can reach the socket, so it may say who the client is
+        server.set_trust_addressless_peers(true);
+        server.on_all(|req, res| {
+            res.text(format!("{:?} {:?}", req.remote_addr(), req.client_ip()));
+
+            Ok(HttpStatusCode::Code200)
+        });
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            Listener::bind_unix(&path, 0o600)
+                .err()
+                .map(|err| err.kind()),
+            Some(std::io::ErrorKind::AddrInUse)
+        );
+
+        // the first connection is the one `bind_unix` made to check the socket is in use
+        let handle = thread::spawn(move || {
+            server.listen_once();
+            server.listen_once();
+        });
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nX-Forwarded-For: 198.51.100.7\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nNone Some(198.51.100.7)"));
+
+        // the socket file goes away with the server
+        handle.join().unwrap();
+        assert!(!path.exists());
+
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(Listener::bind_unix(&path, 0o600).is_err());
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn it_shuts_down_gracefully() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::sync::mpsc;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = Server::from_fd(OwnedFd::from(listener)).unwrap();
+        let (started, has_started) = mpsc::channel();
+        let started = Mutex::new(started);
+        server.on_all(move |_, res| {
+            started.lock().unwrap().send(()).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            res.text("finished");
+
+            Ok(HttpStatusCode::Code200)
+        });
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.listen());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        has_started.recv().unwrap();
+        shutdown.shutdown();
+        assert!(shutdown.is_stopping());
+
+        // `listen` only returns after the request in flight is answered
+        handle.join().unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("\r\n\r\nfinished"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_adopts_inherited_sockets() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("rebar-fd-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let (adopted, pending) = Listener::from_fd(OwnedFd::from(listener)).unwrap();
+        assert!(matches!(adopted, Listener::Unix(_, None)));
+        assert!(pending.is_none());
+        // the socket file belongs to whoever created it
+        drop(adopted);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+
+        // sockets nothing can be accepted on are turned away as well as plain files
+        let rejected = |fd: OwnedFd| Listener::from_fd(fd).err().map(|err| err.kind());
+        let file = std::fs::File::open("./static/index.html").unwrap();
+        assert_eq!(
+            rejected(OwnedFd::from(file)),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            rejected(OwnedFd::from(udp)),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connected = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert_eq!(
+            rejected(OwnedFd::from(connected)),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn it_answers_connections_waiting_on_adopted_sockets() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        // checking the socket accepts the connection, it still has to be answered
+        let mut server = Server::from_fd(OwnedFd::from(listener)).unwrap();
+        server.on_all(|_, res| {
+            res.text("waited");
+
+            Ok(HttpStatusCode::Code200)
+        });
+        server.listen_once();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nwaited"));
+    }
+
+    #[test]
+    fn it_serves_any_transport() {
+        use crate::types::Duplex;
+        use std::io::Cursor;
+
+        let mut server = Server::without_listener();
+        server.on_all(|req, res| {
+            res.text(format!(
+                "{} {:?} {:?} {}",
+                req.path,
+                req.remote_addr(),
+                req.client_ip(),
+                req.body.as_deref().unwrap_or_default()
+            ));
+
+            Ok(HttpStatusCode::Code200)
+        });
+
+        // nothing vouches for the forwarded address, so it's ignored
+        let request =
+            "POST /echo HTTP/1.1\r\nX-Forwarded-For: 6.6.6.6\r\nContent-Length: 5\r\n\r\nhello";
+        let duplex = server.serve_transport(Duplex::new(Cursor::new(request), vec![]));
+        let response = String::from_utf8(duplex.output).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n/echo/ None None hello"));
+
+        let duplex = server.serve_transport(Duplex::new(Cursor::new("nonsense\r\n\r\n"), vec![]));
+        assert!(duplex.output.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
 }
@@ -0,0 +1,386 @@
+use crate::base64;
+use crate::crypto::random_bytes;
+use crate::types::{
+    Cookie, HttpStatusCode, LogError, Middleware, Request, Response, SameSite, Session,
+    SessionRecord, SessionStore, Sessions,
+};
+
+use std::cell::RefMut;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// expired sessions are swept from the store once every this many requests
+const PURGE_INTERVAL: usize = 256;
+
+pub(crate) fn generate_id() -> std::io::Result<String> {
+    Ok(base64::encode(&random_bytes::<32>()?, true))
+}
+
+// ids end up in file names, so anything we didn't generate is rejected up front
+pub(crate) fn is_valid_id(id: &str) -> bool {
+    id.len() == 43
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+impl SessionRecord {
+    pub fn new() -> SessionRecord {
+        let now = SystemTime::now();
+
+        SessionRecord {
+            values: HashMap::new(),
+            created: now,
+            accessed: now,
+        }
+    }
+}
+
+impl Default for SessionRecord {
+    fn default() -> SessionRecord {
+        SessionRecord::new()
+    }
+}
+
+impl Session {
+    pub(crate) fn new(id: Option<String>, record: SessionRecord) -> Session {
+        Session {
+            id,
+            record,
+
+            changed: false,
+            regenerate: false,
+            destroyed: false,
+        }
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    // true until the session has been saved and its cookie sent
+    pub fn is_new(&self) -> bool {
+        self.id.is_none()
+    }
+
+    pub fn created(&self) -> SystemTime {
+        self.record.created
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.record.values.get(key).map(String::as_str)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.record.values.contains_key(key)
+    }
+
+    pub fn insert<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.record.values.insert(key.into(), value.into());
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.record.values.remove(key);
+        self.changed |= value.is_some();
+
+        value
+    }
+
+    pub fn clear(&mut self) {
+        self.changed |= !self.record.values.is_empty();
+        self.record.values.clear();
+    }
+
+    // call this after logging someone in so an id planted before login can't be reused
+    pub fn regenerate(&mut self) {
+        self.regenerate = true;
+    }
+
+    pub fn destroy(&mut self) {
+        self.record.values.clear();
+        self.destroyed = true;
+    }
+}
+
+impl Request {
+    // `None` unless the `Sessions` middleware is installed
+    pub fn session(&self) -> Option<RefMut<'_, Session>> {
+        RefMut::filter_map(self.session.borrow_mut(), Option::as_mut).ok()
+    }
+}
+
+impl Sessions {
+    pub fn new<S>(store: S) -> Sessions
+    where
+        S: SessionStore + 'static,
+    {
+        Sessions {
+            store: Arc::new(store),
+            cookie: Cookie::new("session", "")
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax),
+            idle_timeout: None,
+            absolute_timeout: None,
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn cookie(mut self, cookie: Cookie) -> Sessions {
+        self.cookie = cookie;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Duration) -> Sessions {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn absolute_timeout(mut self, timeout: Duration) -> Sessions {
+        self.absolute_timeout = Some(timeout);
+        self
+    }
+
+    pub fn store(&self) -> &dyn SessionStore {
+        self.store.as_ref()
+    }
+
+    fn is_expired(&self, record: &SessionRecord, now: SystemTime) -> bool {
+        let elapsed = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+
+        self.idle_timeout
+            .is_some_and(|timeout| elapsed(record.accessed) > timeout)
+            || self
+                .absolute_timeout
+                .is_some_and(|timeout| elapsed(record.created) > timeout)
+    }
+
+    pub fn purge_expired(&self) -> std::io::Result<()> {
+        if self.idle_timeout.is_none() && self.absolute_timeout.is_none() {
+            return Ok(());
+        }
+
+        let now = SystemTime::now();
+        self.store.purge(&|record| self.is_expired(record, now))
+    }
+
+    fn session_cookie(&self, id: &str) -> Cookie {
+        let mut cookie = self.cookie.clone();
+        cookie.value = id.to_owned();
+
+        cookie
+    }
+
+    fn load(&self, req: &Request) -> std::io::Result<Session> {
+        let cookies = req.cookies();
+        let id = match cookies.get(&self.cookie.name) {
+            Some(id) if is_valid_id(id) => id,
+            _ => return Ok(Session::new(None, SessionRecord::new())),
+        };
+
+        match self.store.load(id)? {
+            Some(record) if self.is_expired(&record, SystemTime::now()) => {
+                self.store.remove(id)?;
+                Ok(Session::new(None, SessionRecord::new()))
+            }
+            Some(record) => Ok(Session::new(Some(id.to_owned()), record)),
+            None => Ok(Session::new(None, SessionRecord::new())),
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn before(&self, req: &mut Request, _res: &mut Response) -> Option<HttpStatusCode> {
+        match self.load(req) {
+            Ok(session) => {
+                req.session.replace(Some(session));
+                None
+            }
+            Err(err) => {
+                println!("Error {:?}", err);
+                Some(HttpStatusCode::Code500)
+            }
+        }
+    }
+
+    fn after(&self, req: &Request, res: &mut Response) {
+        if self.requests.fetch_add(1, Ordering::Relaxed) % PURGE_INTERVAL == PURGE_INTERVAL - 1 {
+            self.purge_expired().log_error();
+        }
+
+        let session = match req.session.take() {
+            Some(session) => session,
+            None => return,
+        };
+
+        if session.destroyed {
+            if let Some(id) = &session.id {
+                self.store.remove(id).log_error();
+                res.remove_cookie(self.session_cookie(""));
+            }
+            return;
+        }
+
+        let mut id = session.id;
+        if session.regenerate {
+            if let Some(old) = id.take() {
+                self.store.remove(&old).log_error();
+            }
+        }
+
+        // visitors that never store anything don't get a cookie
+        if id.is_none() && session.record.values.is_empty() {
+            return;
+        }
+
+        let mut record = session.record;
+        record.accessed = SystemTime::now();
+
+        match id {
+            Some(id) => {
+                // the access time only has to be written when it's used for expiry
+                if session.changed || self.idle_timeout.is_some() {
+                    self.store.save(&id, &record).log_error();
+                }
+            }
+            None => {
+                let saved = generate_id().and_then(|id| self.store.save(&id, &record).map(|_| id));
+                match saved {
+                    Ok(id) => {
+                        res.set_cookie(self.session_cookie(&id));
+                    }
+                    Err(err) => println!("Error {:?}", err),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+    use crate::types::{HeaderMethods, MemoryStore};
+
+    // runs one request through the middleware and returns the session cookie it set, if any
+    fn round_trip<H>(sessions: &Sessions, cookie: Option<&str>, handler: H) -> Option<Cookie>
+    where
+        H: FnOnce(&mut Session),
+    {
+        let mut req = Request::default();
+        if let Some(cookie) = cookie {
+            req.headers
+                .set_header("Cookie".to_owned(), format!("session={}", cookie));
+        }
+        let mut res = test_response();
+
+        assert_eq!(sessions.before(&mut req, &mut res), None);
+        handler(&mut req.session().unwrap());
+        sessions.after(&req, &mut res);
+
+        res.cookies().first().cloned()
+    }
+
+    #[test]
+    fn it_issues_and_loads_sessions() {
+        let sessions = Sessions::new(MemoryStore::new());
+
+        assert!(Request::default().session().is_none());
+        assert_eq!(
+            round_trip(&sessions, None, |session| assert!(session.is_new())),
+            None
+        );
+
+        let cookie = round_trip(&sessions, None, |session| session.insert("user", "42")).unwrap();
+        assert_eq!(cookie.name, "session");
+        assert_eq!(cookie.path.as_deref(), Some("/"));
+        assert!(cookie.http_only);
+        assert!(is_valid_id(&cookie.value));
+
+        let again = round_trip(&sessions, Some(&cookie.value), |session| {
+            assert_eq!(session.id(), Some(cookie.value.as_str()));
+            assert_eq!(session.get("user"), Some("42"));
+            session.insert("theme", "dark");
+        });
+        assert_eq!(again, None);
+
+        let record = sessions.store().load(&cookie.value).unwrap().unwrap();
+        assert_eq!(record.values.get("theme").map(String::as_str), Some("dark"));
+
+        // unknown or malformed ids start over
+        round_trip(&sessions, Some("forged"), |session| {
+            assert!(session.is_new())
+        });
+        round_trip(&sessions, Some(&generate_id().unwrap()), |session| {
+            assert!(session.is_new())
+        });
+    }
+
+    #[test]
+    fn it_regenerates_and_destroys_sessions() {
+        let sessions = Sessions::new(MemoryStore::new());
+        let old = round_trip(&sessions, None, |session| session.insert("cart", "3")).unwrap();
+
+        let new = round_trip(&sessions, Some(&old.value), |session| {
+            session.insert("user", "42");
+            session.regenerate();
+        })
+        .unwrap();
+        assert_ne!(new.value, old.value);
+        assert_eq!(sessions.store().load(&old.value).unwrap(), None);
+
+        round_trip(&sessions, Some(&new.value), |session| {
+            assert_eq!(session.get("cart"), Some("3"));
+            assert_eq!(session.get("user"), Some("42"));
+        });
+
+        let removed = round_trip(&sessions, Some(&new.value), Session::destroy).unwrap();
+        assert_eq!(removed.value, "");
+        assert_eq!(removed.max_age, Some(Duration::ZERO));
+        assert_eq!(sessions.store().load(&new.value).unwrap(), None);
+    }
+
+    #[test]
+    fn it_expires_sessions() {
+        let sessions = Sessions::new(MemoryStore::new())
+            .idle_timeout(Duration::from_secs(60))
+            .absolute_timeout(Duration::from_secs(3600));
+        let hour_ago = SystemTime::now() - Duration::from_secs(3601);
+
+        let idle = generate_id().unwrap();
+        let mut record = SessionRecord::new();
+        record.accessed = hour_ago;
+        sessions.store().save(&idle, &record).unwrap();
+
+        let old = generate_id().unwrap();
+        let mut record = SessionRecord::new();
+        record.created = hour_ago;
+        sessions.store().save(&old, &record).unwrap();
+
+        let fresh = generate_id().unwrap();
+        sessions
+            .store()
+            .save(&fresh, &SessionRecord::new())
+            .unwrap();
+
+        round_trip(&sessions, Some(&idle), |session| assert!(session.is_new()));
+        assert_eq!(sessions.store().load(&idle).unwrap(), None);
+        round_trip(
+            &sessions,
+            Some(&fresh),
+            |session| assert!(!session.is_new()),
+        );
+
+        sessions.purge_expired().unwrap();
+        assert_eq!(sessions.store().load(&old).unwrap(), None);
+        assert!(sessions.store().load(&fresh).unwrap().is_some());
+    }
+}
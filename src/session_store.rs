@@ -0,0 +1,261 @@
+use crate::json_object;
+use crate::session::is_valid_id;
+use crate::types::{FileStore, JsonValue, MemoryStore, SessionRecord, SessionStore};
+
+use std::collections::HashMap;
+use std::fs::{read_dir, read_to_string, remove_file, rename, DirBuilder, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        Ok(self.sessions.lock().unwrap().get(id).cloned())
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.to_owned(), record.clone());
+
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+
+        Ok(())
+    }
+
+    fn purge(&self, expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, record| !expired(record));
+
+        Ok(())
+    }
+}
+
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn from_secs(value: Option<&JsonValue>) -> Option<SystemTime> {
+    let secs = value?.as_f64()?;
+    if secs < 0.0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+fn serialize(record: &SessionRecord) -> String {
+    let values = record
+        .values
+        .iter()
+        .map(|(key, value)| (key.clone(), JsonValue::from(value.as_str())))
+        .collect();
+
+    json_object! {
+        "created" => to_secs(record.created),
+        "accessed" => to_secs(record.accessed),
+        "values" => JsonValue::Object(values),
+    }
+    .to_string()
+}
+
+fn deserialize(data: &str) -> Option<SessionRecord> {
+    let json = JsonValue::parse_raw(data).ok()?;
+
+    let mut values = HashMap::new();
+    for (key, value) in json.get("values")?.as_object()? {
+        values.insert(key.clone(), value.as_str()?.to_owned());
+    }
+
+    Some(SessionRecord {
+        values,
+        created: from_secs(json.get("created"))?,
+        accessed: from_secs(json.get("accessed"))?,
+    })
+}
+
+impl FileStore {
+    // one json file per session is kept in `dir`, which is created if needed; sessions are
+    // private, so on unix a new directory and the files in it are only open to their owner
+    pub fn new<P>(dir: P) -> io::Result<FileStore>
+    where
+        P: Into<PathBuf>,
+    {
+        let dir = dir.into();
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(&dir)?;
+
+        Ok(FileStore { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if !is_valid_id(id) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid session id",
+            ));
+        }
+
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+
+        match read_to_string(self.path(id)?) {
+            // a corrupt file is treated like a missing session
+            Ok(data) => Ok(deserialize(&data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // written to a temp file first so readers never see half a session
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        let path = self.path(id)?;
+        let tmp = self.dir.join(format!(
+            ".{}.{}.tmp",
+            id,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        options
+            .open(&tmp)?
+            .write_all(serialize(record).as_bytes())?;
+        rename(&tmp, path).inspect_err(|_| {
+            let _ = remove_file(&tmp);
+        })
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match remove_file(self.path(id)?) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn purge(&self, expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()> {
+        for entry in read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_session = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(is_valid_id)
+                && path.extension().is_some_and(|ext| ext == "json");
+            if !is_session {
+                continue;
+            }
+
+            let keep = match read_to_string(&path) {
+                Ok(data) => deserialize(&data).is_some_and(|record| !expired(&record)),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            if !keep {
+                match remove_file(&path) {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::session::generate_id;
+    use crate::tempfile::TempDir;
+
+    #[test]
+    fn it_stores_sessions_in_files() {
+        let tmp = TempDir::new();
+        let dir = tmp.path().join("sessions");
+        let store = FileStore::new(&dir).unwrap();
+
+        let id = generate_id().unwrap();
+        let mut record = SessionRecord::new();
+        record.values.insert("user".into(), "42 \"quoted\"".into());
+        record.created = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert_eq!(store.load(&id).unwrap(), None);
+        store.save(&id, &record).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&dir), 0o700);
+            assert_eq!(mode(&store.path(&id).unwrap()), 0o600);
+        }
+
+        let loaded = store.load(&id).unwrap().unwrap();
+        assert_eq!(loaded.values, record.values);
+        assert_eq!(loaded.created, record.created);
+        assert_eq!(to_secs(loaded.accessed), to_secs(record.accessed));
+
+        assert_eq!(store.load("../../etc/passwd").unwrap(), None);
+        assert!(store.save("../escape", &record).is_err());
+
+        let other = generate_id().unwrap();
+        store.save(&other, &SessionRecord::new()).unwrap();
+        store
+            .purge(&|record| record.created < UNIX_EPOCH + Duration::from_secs(2_000_000))
+            .unwrap();
+        assert_eq!(store.load(&id).unwrap(), None);
+        assert!(store.load(&other).unwrap().is_some());
+
+        store.remove(&other).unwrap();
+        store.remove(&other).unwrap();
+        assert_eq!(read_dir(&dir).unwrap().count(), 0);
+    }
+}
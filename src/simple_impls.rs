@@ -1,8 +1,9 @@
 use crate::types::{
     HeaderMethods, Headers, HttpParseError, HttpResponseError, HttpStatusCode, HttpVersion,
-    LogError, Method, NormalizePath, RebarError, Request, TemplateError,
+    JsonError, LogError, Method, NormalizePath, RebarError, Request, TemplateError,
 };
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
@@ -20,9 +21,28 @@ impl Display for HttpVersion {
     }
 }
 
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{}",
+            match self {
+                Method::Get => "GET",
+                Method::Post => "POST",
+                Method::Put => "PUT",
+                Method::Head => "HEAD",
+                Method::Delete => "DELETE",
+                Method::Options => "OPTIONS",
+                Method::Patch => "PATCH",
+            }
+        )
+    }
+}
+
 impl Error for RebarError {}
 impl Error for HttpParseError {}
 impl Error for TemplateError {}
+impl Error for JsonError {}
 
 impl Display for RebarError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
@@ -32,6 +52,7 @@ impl Display for RebarError {
             match self {
                 RebarError::ParseError(parse_err) => parse_err.to_string(),
                 RebarError::TemplateError(template_err) => template_err.to_string(),
+                RebarError::JsonError(json_err) => json_err.to_string(),
             }
         )
     }
@@ -58,88 +79,63 @@ impl Display for TemplateError {
     }
 }
 
-impl Display for HttpParseError {
+impl Display for JsonError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         write!(
             f,
             "{}",
             match self {
-                Self::InvalidMethod => "Invalid method",
-                Self::InvalidPath => "Invalid path",
-                Self::InvalidHttpVersion => "Invalid http version",
-                Self::InvalidHeaderSyntax => "Invalid header syntax",
-                Self::Other(err) => err,
+                Self::UnexpectedChar(c, pos) => format!("Unexpected char '{}' at {}", c, pos),
+                Self::UnexpectedEof => "Unexpected end of input".to_string(),
+                Self::InvalidNumber(pos) => format!("Invalid number at {}", pos),
+                Self::InvalidEscape(pos) => format!("Invalid escape sequence at {}", pos),
+                Self::InvalidUnicode(pos) => format!("Invalid unicode escape at {}", pos),
+                Self::TrailingCharacters(pos) => format!("Unexpected trailing input at {}", pos),
+                Self::TooDeep => "Nesting is too deep".to_string(),
             }
         )
     }
 }
 
-impl Display for HttpStatusCode {
+impl Display for HttpParseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        use HttpStatusCode::*;
         write!(
             f,
             "{}",
             match self {
-                Code100 => "100 Continue",
-                Code101 => "101 Switching Protocols",
-                Code103 => "103 Early Hints",
-                Code200 => "200 OK",
-                Code201 => "201 Created",
-                Code202 => "202 Accepted",
-                Code203 => "203 Non-Authoritative Information",
-                Code204 => "204 No Content",
-                Code205 => "205 Reset Content",
-                Code206 => "206 Partial Content",
-                Code300 => "300 Multiple Choices",
-                Code301 => "301 Moved Permanently",
-                Code302 => "302 Found",
-                Code303 => "303 See Other",
-                Code304 => "304 Not Modified",
-                Code307 => "307 Temporary Redirect",
-                Code308 => "308 Permanent Redirect",
-                Code400 => "400 Bad Request",
-                Code401 => "401 Unauthorized",
-                Code402 => "402 Payment Required",
-                Code403 => "403 Forbidden",
-                Code404 => "404 Not Found",
-                Code405 => "405 Method Not Allowed",
-                Code406 => "406 Not Acceptable",
-                Code407 => "407 Proxy Authentication Required",
-                Code408 => "408 Request Timeout",
-                Code409 => "409 Conflict",
-                Code410 => "410 Gone",
-                Code411 => "411 Length Required",
-                Code412 => "412 Precondition Failed",
-                Code413 => "413 Payload Too Large",
-                Code414 => "414 URI Too Long",
-                Code415 => "415 Unsupported Media Type",
-                Code416 => "416 Range Not Satisfiable",
-                Code417 => "417 Expectation Failed",
-                Code418 => "418 I'm a teapot",
-                Code422 => "422 Unprocessable Entity",
-                Code425 => "425 Too Early",
-                Code426 => "426 Upgrade Required",
-                Code428 => "428 Precondition Required",
-                Code429 => "429 Too Many Requests",
-                Code431 => "431 Request Header Fields Too Large",
-                Code451 => "451 Unavailable For Legal Reasons",
-                Code500 => "500 Internal Server Error",
-                Code501 => "501 Not Implemented",
-                Code502 => "502 Bad Gateway",
-                Code503 => "503 Service Unavailable",
-                Code504 => "504 Gateway Timeout",
-                Code505 => "505 HTTP Version Not Supported",
-                Code506 => "506 Variant Also Negotiates",
-                Code507 => "507 Insufficient Storage",
-                Code508 => "508 Loop Detected",
-                Code510 => "510 Not Extended",
-                Code511 => "511 Network Authentication Required",
+                Self::InvalidMethod => "Invalid method".to_string(),
+                Self::InvalidPath => "Invalid path".to_string(),
+                Self::InvalidHttpVersion => "Invalid http version".to_string(),
+                Self::InvalidHeaderSyntax => "Invalid header syntax".to_string(),
+                Self::InvalidContentLength => "Invalid content length".to_string(),
+                Self::InvalidChunkedEncoding => "Invalid chunked encoding".to_string(),
+                Self::HeadersTooLarge => "Request headers are too large".to_string(),
+                Self::PayloadTooLarge => "Request body is too large".to_string(),
+                Self::UnsupportedEncoding(coding) =>
+                    format!("Unsupported content encoding `{}`", coding),
+                Self::InvalidEncodedBody => "Invalid compressed body".to_string(),
+
+                Self::UnsupportedMediaType(Some(content_type)) =>
+                    format!("Unsupported media type `{}`", content_type),
+                Self::UnsupportedMediaType(None) => "Missing content type".to_string(),
+                Self::InvalidJson(err) => format!("Invalid json body: {}", err),
+                Self::InvalidMultipart(err) => format!("Invalid multipart body: {}", err),
+
+                Self::Other(err) => err.to_string(),
             }
         )
     }
 }
 
+impl Display for HttpStatusCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.reason() {
+            "" => write!(f, "{}", self.code),
+            reason => write!(f, "{} {}", self.code, reason),
+        }
+    }
+}
+
 impl Display for Headers {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         let mut string = String::new();
@@ -164,6 +160,15 @@ impl Default for Request {
             headers: Headers(HashMap::new()),
 
             body: None,
+            raw_body: None,
+
+            session: RefCell::new(None),
+            csp_nonce: None,
+            csrf_secret: None,
+            remote_addr: None,
+            local_addr: None,
+            client_ip: None,
+            secure: false,
         }
     }
 }
@@ -177,6 +182,15 @@ impl<T> LogError for Result<T, HttpResponseError> {
     }
 }
 
+impl<T> LogError for std::io::Result<T> {
+    fn log_error(&self) {
+        match self {
+            Ok(_) => {}
+            Err(err) => println!("Error {:?}", err),
+        }
+    }
+}
+
 impl NormalizePath for String {
     fn normalize(&self) -> (String, Option<String>, Option<String>) {
         fn fix_path(path: &str) -> String {
@@ -200,7 +214,7 @@ impl NormalizePath for String {
             None => "/".to_owned(),
         };
 
-        let query = match self.split('?').skip(1).next() {
+        let query = match self.split('?').nth(1) {
             Some(s) => match s.split('#').next() {
                 Some(s) if s.trim() != "" => Some(s.trim().to_owned()),
                 _ => None,
@@ -208,7 +222,7 @@ impl NormalizePath for String {
             None => None,
         };
 
-        let fragment = match self.splitn(2, '#').skip(1).next() {
+        let fragment = match self.split_once('#').map(|x| x.1) {
             Some(s) if s.trim() != "" => Some(s.trim().to_owned()),
             _ => None,
         };
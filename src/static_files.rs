@@ -0,0 +1,464 @@
+use crate::conditional::file_etag;
+use crate::form::{percent_decode, percent_encode_path};
+use crate::mime::content_type;
+use crate::types::{
+    HeaderMethods, HttpStatusCode, Method, Middleware, Request, Response, StaticFiles, Template,
+};
+
+use std::fs::{canonicalize, metadata, read_dir, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+fn read_range(path: &Path, first: u64, last: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(first))?;
+
+    let mut data = vec![0; (last - first + 1) as usize];
+    file.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+// the validators come from the metadata so unchanged files are never read,
+// and for range requests only the requested bytes are
+fn serve_file(path: &Path, req: &Request, res: &mut Response) -> HttpStatusCode {
+    if let Ok(metadata) = metadata(path) {
+        let modified = metadata.modified().ok();
+        let etag = file_etag(metadata.len(), modified);
+
+        if let Some(status) = res.preconditions(req, Some(&etag), modified) {
+            return status;
+        }
+
+        let ranges = req.byte_ranges(metadata.len(), Some(&etag), modified);
+        let content_type = content_type(path).to_owned();
+        match res.send_ranges(ranges, metadata.len(), Some(content_type), |first, last| {
+            read_range(path, first, last)
+        }) {
+            Ok(Some(status)) => return status,
+            Ok(None) => {}
+            Err(_) => return HttpStatusCode::Code500,
+        }
+    }
+
+    res.file(path)
+}
+
+impl StaticFiles {
+    // serves the files in `root` under the url `prefix`, e.g. `StaticFiles::new("/static", "./static")`
+    pub fn new<S, P>(prefix: S, root: P) -> StaticFiles
+    where
+        S: Into<String>,
+        P: Into<PathBuf>,
+    {
+        let prefix = prefix.into();
+        let prefix = format!("/{}/", prefix.trim_matches('/')).replace("//", "/");
+
+        StaticFiles {
+            prefix,
+            root: root.into(),
+            index: Some("index.html".to_owned()),
+            listings: false,
+        }
+    }
+
+    pub fn index<S>(mut self, file: S) -> StaticFiles
+    where
+        S: Into<String>,
+    {
+        self.index = Some(file.into());
+        self
+    }
+
+    pub fn no_index(mut self) -> StaticFiles {
+        self.index = None;
+        self
+    }
+
+    pub fn listings(mut self, listings: bool) -> StaticFiles {
+        self.listings = listings;
+        self
+    }
+
+    // `None` when the request isn't under the prefix, so it can be used inside a handler too
+    pub fn serve(&self, req: &Request, res: &mut Response) -> Option<HttpStatusCode> {
+        let rest = req.path.strip_prefix(&self.prefix)?;
+
+        let status = match req.method {
+            Method::Get | Method::Head => match self.resolve(rest) {
+                Ok(path) if path.is_dir() => self.serve_dir(&path, req, res),
+                Ok(path) => serve_file(&path, req, res),
+                Err(status) => status,
+            },
+            _ => {
+                res.headers.set_header("Allow", "GET, HEAD");
+                HttpStatusCode::Code405
+            }
+        };
+
+        if req.method == Method::Head {
+            res.body.clear();
+        }
+        res.status = status.clone();
+
+        Some(status)
+    }
+
+    fn resolve(&self, rest: &str) -> Result<PathBuf, HttpStatusCode> {
+        let mut path = self.root.clone();
+
+        // segments are decoded one at a time so an escaped `/` can't sneak in a `..`
+        for segment in rest.split('/') {
+            match percent_decode(segment, false).as_str() {
+                "" | "." => {}
+                ".." => return Err(HttpStatusCode::Code403),
+                segment if segment.contains(['/', '\\', '\0']) => {
+                    return Err(HttpStatusCode::Code403)
+                }
+                segment => path.push(segment),
+            }
+        }
+
+        // symlinks may still point outside of the root
+        let root = canonicalize(&self.root).map_err(|_| HttpStatusCode::Code404)?;
+        let path = canonicalize(path).map_err(|_| HttpStatusCode::Code404)?;
+        if !path.starts_with(root) {
+            return Err(HttpStatusCode::Code403);
+        }
+
+        Ok(path)
+    }
+
+    fn serve_dir(&self, dir: &Path, req: &Request, res: &mut Response) -> HttpStatusCode {
+        if let Some(index) = &self.index {
+            let index = dir.join(index);
+            if index.is_file() {
+                return serve_file(&index, req, res);
+            }
+        }
+
+        if !self.listings {
+            return HttpStatusCode::Code403;
+        }
+
+        let mut entries = match read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let name = entry.file_name().into_string().ok()?;
+                    let is_dir = entry.file_type().ok()?.is_dir();
+
+                    (!name.starts_with('.')).then_some((is_dir, name))
+                })
+                .collect::<Vec<_>>(),
+            Err(_) => return HttpStatusCode::Code403,
+        };
+        entries.sort_by(|(a_dir, a), (b_dir, b)| b_dir.cmp(a_dir).then(a.cmp(b)));
+
+        let url = percent_decode(&req.path, false);
+        let title = Template::sanitize(&url);
+        let mut list = String::new();
+        // links are absolute since the trailing `/` of the url can't be relied on
+        if let Some((parent, _)) = url.trim_end_matches('/').rsplit_once('/') {
+            if url != self.prefix {
+                list.push_str(&format!(
+                    "<li><a href=\"{}/\">../</a></li>\n",
+                    percent_encode_path(parent)
+                ));
+            }
+        }
+        for (is_dir, name) in entries {
+            let name = if is_dir { name + "/" } else { name };
+            list.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                percent_encode_path(&(url.clone() + &name)),
+                Template::sanitize(&name)
+            ));
+        }
+
+        res.html(format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n{1}</ul>\n</body>\n</html>\n",
+            title, list
+        ))
+    }
+}
+
+impl Middleware for StaticFiles {
+    fn before(&self, req: &mut Request, res: &mut Response) -> Option<HttpStatusCode> {
+        self.serve(req, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::respond::test_response;
+    use crate::tempfile::TempDir;
+    use crate::types::{Compression, Server, TestRequest};
+
+    use std::fs::{create_dir_all, write};
+
+    type Handler = fn(
+        &Request,
+        &mut Response,
+    ) -> std::result::Result<HttpStatusCode, Box<dyn std::error::Error>>;
+
+    fn get(files: &StaticFiles, method: Method, path: &str) -> Option<(u16, Response)> {
+        get_with(files, method, path, &[])
+    }
+
+    fn get_with(
+        files: &StaticFiles,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Option<(u16, Response)> {
+        let mut req = Request {
+            method,
+            path: path.to_owned(),
+            ..Default::default()
+        };
+        for (name, value) in headers {
+            req.headers.set_header(*name, *value);
+        }
+        let mut res = test_response();
+
+        files
+            .serve(&req, &mut res)
+            .map(|status| (status.as_u16(), res))
+    }
+
+    #[test]
+    fn it_serves_static_files() {
+        let dir = TempDir::new();
+        let root = dir.path().join("public");
+        create_dir_all(root.join("docs")).unwrap();
+        create_dir_all(root.join("empty dir")).unwrap();
+        write(root.join("style.css"), "body {}").unwrap();
+        write(root.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        write(root.join(".env"), "hidden").unwrap();
+        write(dir.path().join("secret.txt"), "secret").unwrap();
+
+        let files = StaticFiles::new("/assets", &root);
+        assert!(get(&files, Method::Get, "/other/style.css/").is_none());
+        assert!(get(&files, Method::Get, "/assetsx/style.css/").is_none());
+
+        let (status, res) = get(&files, Method::Get, "/assets/style.css/").unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(res.body, b"body {}");
+        assert_eq!(
+            res.headers.get_header("Content-Type").map(String::as_str),
+            Some("text/css; charset=utf-8")
+        );
+
+        let (status, res) = get(&files, Method::Head, "/assets/style.css/").unwrap();
+        assert_eq!(status, 200);
+        assert!(res.body.is_empty());
+
+        let (status, res) = get(&files, Method::Get, "/assets/docs/").unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(res.body, b"<h1>docs</h1>");
+
+        assert_eq!(
+            get(&files, Method::Get, "/assets/missing.js/").unwrap().0,
+            404
+        );
+        assert_eq!(
+            get(&files, Method::Get, "/assets/empty%20dir/").unwrap().0,
+            403
+        );
+        assert_eq!(
+            get(&files, Method::Get, "/assets/../secret.txt/")
+                .unwrap()
+                .0,
+            403
+        );
+        assert_eq!(
+            get(&files, Method::Get, "/assets/%2e%2e/secret.txt/")
+                .unwrap()
+                .0,
+            403
+        );
+        assert_eq!(
+            get(&files, Method::Get, "/assets/docs%2F..%2F..%2Fsecret.txt/")
+                .unwrap()
+                .0,
+            403
+        );
+
+        let (status, res) = get(&files, Method::Post, "/assets/style.css/").unwrap();
+        assert_eq!(status, 405);
+        assert_eq!(
+            res.headers.get_header("Allow").map(String::as_str),
+            Some("GET, HEAD")
+        );
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("link.txt"))
+                .unwrap();
+            assert_eq!(
+                get(&files, Method::Get, "/assets/link.txt/").unwrap().0,
+                403
+            );
+        }
+
+        let files = StaticFiles::new("assets/", &root).listings(true);
+        let (status, res) = get(&files, Method::Get, "/assets/").unwrap();
+        let listing = String::from_utf8(res.body).unwrap();
+        assert_eq!(status, 200);
+        assert!(listing.contains("<a href=\"/assets/docs/\">docs/</a>"));
+        assert!(listing.contains("<a href=\"/assets/empty%20dir/\">empty dir/</a>"));
+        assert!(listing.contains("<a href=\"/assets/style.css\">style.css</a>"));
+        assert!(listing.find("docs/").unwrap() < listing.find("style.css").unwrap());
+        assert!(!listing.contains(".env"));
+        assert!(!listing.contains("../"));
+
+        let (_, res) = get(&files, Method::Get, "/assets/empty%20dir/").unwrap();
+        assert!(String::from_utf8(res.body)
+            .unwrap()
+            .contains("<a href=\"/assets/\">../</a>"));
+    }
+
+    #[test]
+    fn it_revalidates_static_files() {
+        let tmp = TempDir::new();
+        let root = tmp.path();
+        write(root.join("app.js"), "run()").unwrap();
+
+        let files = StaticFiles::new("/", root);
+        let (status, res) = get(&files, Method::Get, "/app.js/").unwrap();
+        assert_eq!(status, 200);
+        let etag = res.headers.get_header("ETag").cloned().unwrap();
+        let modified = res.headers.get_header("Last-Modified").cloned().unwrap();
+
+        let (status, res) =
+            get_with(&files, Method::Get, "/app.js/", &[("If-None-Match", &etag)]).unwrap();
+        assert_eq!(status, 304);
+        assert!(res.body.is_empty());
+        assert_eq!(res.headers.get_header("ETag"), Some(&etag));
+
+        let (status, _) = get_with(
+            &files,
+            Method::Get,
+            "/app.js/",
+            &[("If-Modified-Since", &modified)],
+        )
+        .unwrap();
+        assert_eq!(status, 304);
+
+        let (status, res) = get_with(
+            &files,
+            Method::Get,
+            "/app.js/",
+            &[("If-None-Match", "\"stale\"")],
+        )
+        .unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(res.body, b"run()");
+    }
+
+    #[test]
+    fn it_revalidates_compressed_static_files() {
+        let tmp = TempDir::new();
+        let root = tmp.path();
+        write(root.join("app.js"), "run();".repeat(1000)).unwrap();
+
+        let mut server: Server<Handler> = Server::without_listener();
+        server.add_middleware(Compression::new());
+        server.add_middleware(StaticFiles::new("/", root));
+
+        let get = || TestRequest::get("/app.js").header("Accept-Encoding", "gzip");
+        let res = server.test(get());
+        res.assert_status(HttpStatusCode::Code200)
+            .assert_header("Content-Encoding", "gzip");
+        let etag = res.header("ETag").unwrap().to_owned();
+        assert!(etag.ends_with("-gzip\""));
+
+        server
+            .test(get().header("If-None-Match", &etag))
+            .assert_status(HttpStatusCode::Code304)
+            .assert_body("");
+
+        // the byte ranges are of the uncompressed file, so a range can't continue the gzip body
+        server
+            .test(get().header("Range", "bytes=0-1").header("If-Range", &etag))
+            .assert_status(HttpStatusCode::Code200)
+            .assert_header("Content-Encoding", "gzip");
+    }
+
+    #[test]
+    fn it_serves_file_ranges() {
+        let tmp = TempDir::new();
+        let root = tmp.path();
+        write(root.join("video.mp4"), "0123456789").unwrap();
+
+        let files = StaticFiles::new("/media", root);
+        let (status, res) = get(&files, Method::Get, "/media/video.mp4/").unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(
+            res.headers.get_header("Accept-Ranges").map(String::as_str),
+            Some("bytes")
+        );
+        let etag = res.headers.get_header("ETag").cloned().unwrap();
+
+        let (status, res) = get_with(
+            &files,
+            Method::Get,
+            "/media/video.mp4/",
+            &[("Range", "bytes=-3")],
+        )
+        .unwrap();
+        assert_eq!(status, 206);
+        assert_eq!(res.body, b"789");
+        assert_eq!(
+            res.headers.get_header("Content-Range").map(String::as_str),
+            Some("bytes 7-9/10")
+        );
+        assert_eq!(
+            res.headers.get_header("Content-Type").map(String::as_str),
+            Some("video/mp4")
+        );
+
+        let (status, res) = get_with(
+            &files,
+            Method::Get,
+            "/media/video.mp4/",
+            &[("Range", "bytes=0-1,4-5")],
+        )
+        .unwrap();
+        assert_eq!(status, 206);
+        let body = String::from_utf8(res.body).unwrap();
+        assert!(body.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n"));
+        assert!(body.contains("Content-Range: bytes 4-5/10\r\n\r\n45\r\n"));
+
+        let (status, _) = get_with(
+            &files,
+            Method::Get,
+            "/media/video.mp4/",
+            &[("Range", "bytes=10-")],
+        )
+        .unwrap();
+        assert_eq!(status, 416);
+
+        let (status, res) = get_with(
+            &files,
+            Method::Get,
+            "/media/video.mp4/",
+            &[("Range", "bytes=0-1"), ("If-Range", "\"old\"")],
+        )
+        .unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(res.body, b"0123456789");
+
+        let (status, _) = get_with(
+            &files,
+            Method::Get,
+            "/media/video.mp4/",
+            &[("Range", "bytes=0-1"), ("If-Range", &etag)],
+        )
+        .unwrap();
+        assert_eq!(status, 206);
+    }
+}
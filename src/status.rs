@@ -0,0 +1,194 @@
+use crate::types::{HttpResponseError, HttpStatusCode};
+
+macro_rules! status_codes {
+    {$($name:ident => ($code:expr, $reason:expr)),* $(,)?} => {
+        #[allow(non_upper_case_globals)]
+        impl HttpStatusCode {
+            $(
+                pub const $name: HttpStatusCode = HttpStatusCode { code: $code };
+            )*
+        }
+
+        fn canonical_reason(code: u16) -> Option<&'static str> {
+            match code {
+                $($code => Some($reason),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+status_codes! {
+    Code100 => (100, "Continue"),
+    Code101 => (101, "Switching Protocols"),
+    Code102 => (102, "Processing"),
+    Code103 => (103, "Early Hints"),
+    Code200 => (200, "OK"),
+    Code201 => (201, "Created"),
+    Code202 => (202, "Accepted"),
+    Code203 => (203, "Non-Authoritative Information"),
+    Code204 => (204, "No Content"),
+    Code205 => (205, "Reset Content"),
+    Code206 => (206, "Partial Content"),
+    Code207 => (207, "Multi-Status"),
+    Code208 => (208, "Already Reported"),
+    Code226 => (226, "IM Used"),
+    Code300 => (300, "Multiple Choices"),
+    Code301 => (301, "Moved Permanently"),
+    Code302 => (302, "Found"),
+    Code303 => (303, "See Other"),
+    Code304 => (304, "Not Modified"),
+    Code305 => (305, "Use Proxy"),
+    Code307 => (307, "Temporary Redirect"),
+    Code308 => (308, "Permanent Redirect"),
+    Code400 => (400, "Bad Request"),
+    Code401 => (401, "Unauthorized"),
+    Code402 => (402, "Payment Required"),
+    Code403 => (403, "Forbidden"),
+    Code404 => (404, "Not Found"),
+    Code405 => (405, "Method Not Allowed"),
+    Code406 => (406, "Not Acceptable"),
+    Code407 => (407, "Proxy Authentication Required"),
+    Code408 => (408, "Request Timeout"),
+    Code409 => (409, "Conflict"),
+    Code410 => (410, "Gone"),
+    Code411 => (411, "Length Required"),
+    Code412 => (412, "Precondition Failed"),
+    Code413 => (413, "Payload Too Large"),
+    Code414 => (414, "URI Too Long"),
+    Code415 => (415, "Unsupported Media Type"),
+    Code416 => (416, "Range Not Satisfiable"),
+    Code417 => (417, "Expectation Failed"),
+    Code418 => (418, "I'm a teapot"),
+    Code421 => (421, "Misdirected Request"),
+    Code422 => (422, "Unprocessable Entity"),
+    Code423 => (423, "Locked"),
+    Code424 => (424, "Failed Dependency"),
+    Code425 => (425, "Too Early"),
+    Code426 => (426, "Upgrade Required"),
+    Code428 => (428, "Precondition Required"),
+    Code429 => (429, "Too Many Requests"),
+    Code431 => (431, "Request Header Fields Too Large"),
+    Code451 => (451, "Unavailable For Legal Reasons"),
+    Code500 => (500, "Internal Server Error"),
+    Code501 => (501, "Not Implemented"),
+    Code502 => (502, "Bad Gateway"),
+    Code503 => (503, "Service Unavailable"),
+    Code504 => (504, "Gateway Timeout"),
+    Code505 => (505, "HTTP Version Not Supported"),
+    Code506 => (506, "Variant Also Negotiates"),
+    Code507 => (507, "Insufficient Storage"),
+    Code508 => (508, "Loop Detected"),
+    Code510 => (510, "Not Extended"),
+    Code511 => (511, "Network Authentication Required"),
+}
+
+impl HttpStatusCode {
+    // any three digit code in the range RFC 9110 allows, registered or not
+    pub fn new(code: u16) -> Option<HttpStatusCode> {
+        if (100..=599).contains(&code) {
+            Some(HttpStatusCode { code })
+        } else {
+            None
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.code
+    }
+
+    pub fn canonical_reason(&self) -> Option<&'static str> {
+        canonical_reason(self.code)
+    }
+
+    // the standard phrase, or nothing for unregistered codes, see `Response::set_reason`
+    pub fn reason(&self) -> &'static str {
+        self.canonical_reason().unwrap_or("")
+    }
+
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code)
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code)
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.code)
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code)
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code)
+    }
+}
+
+impl From<HttpStatusCode> for u16 {
+    fn from(status: HttpStatusCode) -> u16 {
+        status.code
+    }
+}
+
+impl TryFrom<u16> for HttpStatusCode {
+    type Error = HttpResponseError;
+
+    fn try_from(code: u16) -> Result<HttpStatusCode, HttpResponseError> {
+        HttpStatusCode::new(code)
+            .ok_or_else(|| HttpResponseError::Other(format!("Invalid status code {}", code)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_named_codes() {
+        assert_eq!(HttpStatusCode::Code200.as_u16(), 200);
+        assert_eq!(HttpStatusCode::Code404.to_string(), "404 Not Found");
+        assert_eq!(HttpStatusCode::Code207.to_string(), "207 Multi-Status");
+        assert_eq!(HttpStatusCode::new(200), Some(HttpStatusCode::Code200));
+    }
+
+    #[test]
+    fn it_accepts_arbitrary_codes() {
+        assert_eq!(HttpStatusCode::new(99), None);
+        assert_eq!(HttpStatusCode::new(600), None);
+        assert!(HttpStatusCode::try_from(1000).is_err());
+
+        let status = HttpStatusCode::new(499).unwrap();
+        assert_eq!(status.as_u16(), 499);
+        assert_eq!(status.canonical_reason(), None);
+        // codes without a standard phrase print as just the number
+        assert_eq!(status.to_string(), "499");
+        assert_eq!(HttpStatusCode::Code404.to_string(), "404 Not Found");
+    }
+
+    #[test]
+    fn it_matches_named_codes() {
+        let describe = |status: &HttpStatusCode| match *status {
+            HttpStatusCode::Code404 => "missing",
+            HttpStatusCode::Code200 => "fine",
+            _ => "other",
+        };
+        assert_eq!(describe(&HttpStatusCode::Code404), "missing");
+        assert_eq!(describe(&HttpStatusCode::new(200).unwrap()), "fine");
+
+        let status = HttpStatusCode::new(418).unwrap();
+        assert!(matches!(status, HttpStatusCode::Code418));
+    }
+
+    #[test]
+    fn it_classifies_codes() {
+        assert!(HttpStatusCode::Code101.is_informational());
+        assert!(HttpStatusCode::new(299).unwrap().is_success());
+        assert!(HttpStatusCode::Code304.is_redirection());
+        assert!(HttpStatusCode::Code418.is_client_error());
+        assert!(!HttpStatusCode::Code418.is_server_error());
+        assert!(HttpStatusCode::Code503.is_server_error());
+    }
+}
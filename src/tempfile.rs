@@ -0,0 +1,230 @@
+use crate::types::{Spooled, TempFile};
+
+use std::fs::{copy, remove_file, rename, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl TempFile {
+    pub(crate) fn create() -> io::Result<(TempFile, File)> {
+        loop {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+            let path = std::env::temp_dir().join(format!(
+                "rebar-{}-{}-{}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                nanos
+            ));
+
+            let mut options = OpenOptions::new();
+            options.read(true).write(true).create_new(true);
+            // uploads can be private, and the temp dir is usually shared with other users
+            #[cfg(unix)]
+            options.mode(0o600);
+
+            match options.open(&path) {
+                Ok(file) => return Ok((TempFile { path, len: 0 }, file)),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    // moves the file out of the temp dir, after which it is no longer cleaned up
+    pub fn persist<P>(self, to: P) -> io::Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        let to = to.as_ref().to_path_buf();
+
+        // renaming fails across filesystems, so fall back to copying
+        if rename(&self.path, &to).is_err() {
+            copy(&self.path, &to)?;
+        }
+
+        Ok(to)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+// a fresh directory for tests, removed with everything in it even when an assertion fails
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new() -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "rebar-{}-{}.dir",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// buffers in memory and moves to a temp file once `threshold` bytes have been written
+pub(crate) struct Spool {
+    threshold: usize,
+    memory: Vec<u8>,
+    file: Option<(TempFile, File)>,
+}
+
+impl Spool {
+    pub(crate) fn new(threshold: usize) -> Spool {
+        Spool {
+            threshold,
+            memory: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        match &self.file {
+            Some((temp, _)) => temp.len,
+            None => self.memory.len() as u64,
+        }
+    }
+
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.file.is_none() && self.memory.len() + data.len() > self.threshold {
+            let (mut temp, mut file) = TempFile::create()?;
+            file.write_all(&self.memory)?;
+            temp.len = self.memory.len() as u64;
+
+            self.memory = Vec::new();
+            self.file = Some((temp, file));
+        }
+
+        match &mut self.file {
+            Some((temp, file)) => {
+                file.write_all(data)?;
+                temp.len += data.len() as u64;
+            }
+            None => self.memory.extend_from_slice(data),
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> io::Result<Spooled> {
+        match self.file {
+            Some((temp, mut file)) => {
+                file.flush()?;
+                Ok(Spooled::File(temp))
+            }
+            None => Ok(Spooled::Memory(self.memory)),
+        }
+    }
+}
+
+impl Spooled {
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            Spooled::Memory(bytes) => bytes.len() as u64,
+            Spooled::File(temp) => temp.len,
+        }
+    }
+
+    pub(crate) fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match self {
+            Spooled::Memory(bytes) => Ok(Box::new(bytes.as_slice())),
+            Spooled::File(temp) => Ok(Box::new(temp.open()?)),
+        }
+    }
+
+    pub(crate) fn persist(self, to: &Path) -> io::Result<PathBuf> {
+        match self {
+            Spooled::Memory(bytes) => {
+                File::create(to)?.write_all(&bytes)?;
+                Ok(to.to_path_buf())
+            }
+            Spooled::File(temp) => temp.persist(to),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_spills_to_disk() {
+        let mut spool = Spool::new(4);
+        spool.write_all(b"abc").unwrap();
+        assert_eq!(spool.len(), 3);
+        assert!(matches!(
+            Spool::new(4).finish().unwrap(),
+            Spooled::Memory(bytes) if bytes.is_empty()
+        ));
+
+        spool.write_all(b"defg").unwrap();
+        let spooled = spool.finish().unwrap();
+        assert_eq!(spooled.len(), 7);
+
+        let path = match &spooled {
+            Spooled::File(temp) => temp.path().to_path_buf(),
+            Spooled::Memory(_) => panic!("expected the spool to move to disk"),
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut contents = String::new();
+        spooled
+            .reader()
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "abcdefg");
+
+        drop(spooled);
+        assert!(!path.exists());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::types::{
-    RebarError::TemplateError, Result, Template, TemplateComponent, TemplateError::*,
+    RebarError::TemplateError, Request, Result, Template, TemplateComponent, TemplateError::*,
 };
 
 use std::collections::HashMap;
@@ -18,7 +18,7 @@ impl Template {
     where
         P: Into<PathBuf>,
     {
-        let path = PathBuf::from(path.into());
+        let path = path.into();
         let template_file = if path.exists() && path.is_file() {
             read_to_string(path).map_err(|e| TemplateError(ReadErr(e.to_string())))?
         } else {
@@ -58,7 +58,7 @@ impl Template {
                                 curr_token.pop();
                                 curr_token.push('{');
                             } else {
-                                if curr_token.len() != 0 {
+                                if !curr_token.is_empty() {
                                     components
                                         .push(TemplateComponent::TemplatePart(curr_token.clone()));
                                 }
@@ -82,7 +82,7 @@ impl Template {
                     } else {
                         match chars.next() {
                             Some('}') => {
-                                if curr_token.len() == 0 {
+                                if curr_token.is_empty() {
                                     return Err(TemplateError(EmptyVariableName));
                                 } else {
                                     components
@@ -113,7 +113,7 @@ impl Template {
         }
 
         if is_template_text {
-            if curr_token.len() != 0 {
+            if !curr_token.is_empty() {
                 components.push(TemplateComponent::TemplatePart(curr_token));
             }
         } else {
@@ -137,6 +137,22 @@ impl Template {
 
         Ok(soaked_template)
     }
+
+    // also fills `{{ csp_nonce }}` and `{{ csrf_token }}` for this request,
+    // left empty when `SecurityHeaders` or `Csrf` aren't installed
+    pub fn soak_request(&self, req: &Request, mut vars: HashMap<String, String>) -> Result<String> {
+        vars.insert(
+            "csp_nonce".to_owned(),
+            req.csp_nonce().unwrap_or("").to_owned(),
+        );
+        vars.insert(
+            "csrf_token".to_owned(),
+            req.csrf_token().unwrap_or_default(),
+        );
+
+        self.soak(vars)
+    }
+
     pub fn soak_raw(&self, vars: HashMap<String, String>) -> Result<String> {
         let mut soaked_template = String::new();
         for component in self.template.iter() {
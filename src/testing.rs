@@ -0,0 +1,439 @@
+use crate::form::percent_encode_path;
+use crate::server::answer;
+use crate::types::{
+    Cookie, HeaderMethods, HttpStatusCode, JsonValue, Method, Request, Response, Result, Server,
+    TestRequest, TestResponse, Transport,
+};
+
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+
+// feeds a `TestRequest` to the server as if it came over a connection from `remote_addr`,
+// whatever is written back is dropped since the response is taken before it's sent
+struct TestTransport {
+    input: Cursor<Vec<u8>>,
+    remote_addr: Option<SocketAddr>,
+    secure: bool,
+}
+
+impl Read for TestTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for TestTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TestTransport {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    fn is_secure(&self) -> bool {
+        self.secure
+    }
+}
+
+impl TestRequest {
+    // `target` is the path and query as they'd appear in the request line, e.g. `/items?page=2`
+    pub fn new<S>(method: Method, target: S) -> TestRequest
+    where
+        S: Into<String>,
+    {
+        TestRequest {
+            method,
+            target: target.into(),
+            headers: vec![],
+            cookies: vec![],
+            body: vec![],
+            remote_addr: None,
+            secure: false,
+        }
+    }
+
+    pub fn get<S>(target: S) -> TestRequest
+    where
+        S: Into<String>,
+    {
+        TestRequest::new(Method::Get, target)
+    }
+
+    pub fn post<S>(target: S) -> TestRequest
+    where
+        S: Into<String>,
+    {
+        TestRequest::new(Method::Post, target)
+    }
+
+    pub fn put<S>(target: S) -> TestRequest
+    where
+        S: Into<String>,
+    {
+        TestRequest::new(Method::Put, target)
+    }
+
+    pub fn patch<S>(target: S) -> TestRequest
+    where
+        S: Into<String>,
+    {
+        TestRequest::new(Method::Patch, target)
+    }
+
+    pub fn delete<S>(target: S) -> TestRequest
+    where
+        S: Into<String>,
+    {
+        TestRequest::new(Method::Delete, target)
+    }
+
+    pub fn header<N, V>(mut self, name: N, value: V) -> TestRequest
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn cookie<N, V>(mut self, name: N, value: V) -> TestRequest
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.cookies.push((name.into(), value.into()));
+        self
+    }
+
+    // sends every cookie a previous response set, e.g. to carry a session along
+    pub fn cookies_from(mut self, res: &TestResponse) -> TestRequest {
+        for cookie in &res.cookies {
+            self.cookies
+                .push((cookie.name.clone(), cookie.value.clone()));
+        }
+        self
+    }
+
+    pub fn body<B>(mut self, body: B) -> TestRequest
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.body = body.into();
+        self
+    }
+
+    pub fn text<S>(self, body: S) -> TestRequest
+    where
+        S: Into<String>,
+    {
+        self.header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.into())
+    }
+
+    pub fn json<T>(self, body: T) -> TestRequest
+    where
+        T: Display,
+    {
+        self.header("Content-Type", "application/json")
+            .body(body.to_string())
+    }
+
+    pub fn form(self, fields: &[(&str, &str)]) -> TestRequest {
+        let body = fields
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{}={}",
+                    percent_encode_path(name),
+                    percent_encode_path(value)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        self.header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+    }
+
+    // where the request appears to come from, without one `client_ip` is `None` unless
+    // the server trusts peers without an address
+    pub fn remote_addr(mut self, addr: SocketAddr) -> TestRequest {
+        self.remote_addr = Some(addr);
+        self
+    }
+
+    // as if the request came in over tls
+    pub fn secure(mut self) -> TestRequest {
+        self.secure = true;
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let has = |name: &str| {
+            self.headers
+                .iter()
+                .any(|(n, _)| n.eq_ignore_ascii_case(name))
+        };
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, self.target);
+        if !has("Host") {
+            head.push_str("Host: localhost\r\n");
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !self.cookies.is_empty() {
+            let cookies = self
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>();
+            head.push_str(&format!("Cookie: {}\r\n", cookies.join("; ")));
+        }
+        if !self.body.is_empty() && !has("Content-Length") && !has("Transfer-Encoding") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get_header(name).map(String::as_str)
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&Cookie> {
+        self.cookies.iter().find(|cookie| cookie.name == name)
+    }
+
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+
+    pub fn json(&self) -> Result<JsonValue> {
+        JsonValue::parse(self.text())
+    }
+
+    #[track_caller]
+    pub fn assert_status(&self, status: HttpStatusCode) -> &TestResponse {
+        assert!(
+            self.status == status,
+            "expected status `{}`, got `{}` with body {:?}",
+            status,
+            self.status,
+            self.text()
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_header(&self, name: &str, value: &str) -> &TestResponse {
+        assert_eq!(
+            self.header(name),
+            Some(value),
+            "unexpected value for header `{}`",
+            name
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_no_header(&self, name: &str) -> &TestResponse {
+        assert_eq!(self.header(name), None, "header `{}` was sent", name);
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_body<B>(&self, body: B) -> &TestResponse
+    where
+        B: AsRef<[u8]>,
+    {
+        assert!(
+            self.body == body.as_ref(),
+            "expected body {:?}, got {:?}",
+            String::from_utf8_lossy(body.as_ref()),
+            self.text()
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_body_contains(&self, needle: &str) -> &TestResponse {
+        assert!(
+            self.text().contains(needle),
+            "expected body to contain {:?}, got {:?}",
+            needle,
+            self.text()
+        );
+        self
+    }
+}
+
+impl<F> Server<F>
+where
+    F: Fn(
+            &Request,
+            &mut Response,
+        ) -> std::result::Result<HttpStatusCode, Box<dyn std::error::Error>>
+        + Send
+        + 'static,
+{
+    // runs a request through the middleware and handler on the current thread, nothing is
+    // read from or written to a socket; the request is read and set up the same way
+    // `listen` does it, so parser limits, body decoding and proxy handling all apply;
+    // `Server::without_listener` builds a server for this without binding a port
+    pub fn test(&self, req: TestRequest) -> TestResponse {
+        let transport = TestTransport {
+            input: Cursor::new(req.to_bytes()),
+            remote_addr: req.remote_addr,
+            secure: req.secure,
+        };
+        let res = answer(
+            Box::new(transport),
+            &self.handler,
+            &self.middleware,
+            &self.config,
+        );
+
+        TestResponse {
+            status: res.status,
+            headers: res.headers,
+            body: res.body,
+            cookies: res.cookies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::{Authentication, Cors, RateLimit};
+
+    use std::time::Duration;
+
+    type Handler = fn(
+        &Request,
+        &mut Response,
+    ) -> std::result::Result<HttpStatusCode, Box<dyn std::error::Error>>;
+
+    fn server() -> Server<Handler> {
+        let mut server: Server<Handler> = Server::without_listener();
+        server.add_middleware(Cors::new().allow_origin("https://app.example.com"));
+        server.add_middleware(
+            Authentication::bearer("api", |token| token == "secret").under("/admin"),
+        );
+        server.on_all(|req, res| match (&req.method, req.path.as_str()) {
+            (Method::Get, "/hello/") => {
+                let name = req.query.get("name").map_or("world", String::as_str);
+                res.cookies.push(Cookie::new("greeted", name));
+
+                Ok(res.text(format!("hello {}", name)))
+            }
+            (Method::Post, "/echo/") => {
+                let body = req.body.clone().unwrap_or_default();
+                let secure = req.is_secure();
+
+                Ok(res.json(format!("{{\"body\":{:?},\"secure\":{}}}", body, secure)))
+            }
+            (_, "/admin/") => Ok(res.text("welcome")),
+            _ => Ok(HttpStatusCode::Code404),
+        });
+
+        server
+    }
+
+    #[test]
+    fn it_runs_requests_through_the_handler() {
+        let server = server();
+
+        let res = server.test(TestRequest::get("/hello?name=rebar"));
+        res.assert_status(HttpStatusCode::Code200)
+            .assert_header("Content-Type", "text/plain; charset=utf-8")
+            .assert_body("hello rebar");
+        assert_eq!(
+            res.cookie("greeted").map(|c| c.value.as_str()),
+            Some("rebar")
+        );
+
+        let res = server.test(TestRequest::post("/echo").text("ping").secure());
+        let json = res.json().unwrap();
+        assert_eq!(json.get("body").and_then(JsonValue::as_str), Some("ping"));
+        assert_eq!(json.get("secure").and_then(JsonValue::as_bool), Some(true));
+
+        server
+            .test(TestRequest::delete("/nowhere"))
+            .assert_status(HttpStatusCode::Code404);
+        // the parser still sees the raw request, so its limits apply
+        server
+            .test(TestRequest::get("/hello").header("Content-Length", "nope"))
+            .assert_status(HttpStatusCode::Code400);
+    }
+
+    #[test]
+    fn it_runs_requests_through_middleware() {
+        let server = server();
+
+        server
+            .test(TestRequest::get("/admin"))
+            .assert_status(HttpStatusCode::Code401)
+            .assert_header("WWW-Authenticate", "Bearer realm=\"api\"");
+        server
+            .test(TestRequest::get("/admin").header("Authorization", "Bearer secret"))
+            .assert_status(HttpStatusCode::Code200)
+            .assert_body_contains("welcome")
+            .assert_no_header("Access-Control-Allow-Origin");
+
+        server
+            .test(TestRequest::get("/hello").header("Origin", "https://app.example.com"))
+            .assert_header("Access-Control-Allow-Origin", "https://app.example.com");
+
+        let mut limited = Server::without_listener();
+        limited.add_middleware(RateLimit::new(1, Duration::from_secs(60)));
+        limited.on_all(|_, res| Ok(res.text("ok")));
+        let from = |ip: &str| TestRequest::get("/").remote_addr(ip.parse().unwrap());
+        limited
+            .test(from("203.0.113.1:1000"))
+            .assert_status(HttpStatusCode::Code200);
+        limited
+            .test(from("203.0.113.1:1001"))
+            .assert_status(HttpStatusCode::Code429);
+        limited
+            .test(from("203.0.113.2:1000"))
+            .assert_status(HttpStatusCode::Code200);
+    }
+
+    #[test]
+    fn it_carries_cookies_along() {
+        let server = server();
+        let first = server.test(TestRequest::get("/hello?name=a"));
+
+        let req = TestRequest::post("/echo")
+            .cookies_from(&first)
+            .form(&[("q", "a b&c")]);
+        assert!(String::from_utf8_lossy(&req.to_bytes()).contains("Cookie: greeted=a\r\n"));
+
+        server.test(req).assert_body_contains("q=a%20b%26c");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected status `404 Not Found`, got `200 OK`")]
+    fn it_explains_failed_assertions() {
+        server()
+            .test(TestRequest::get("/hello"))
+            .assert_status(HttpStatusCode::Code404);
+    }
+}
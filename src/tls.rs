@@ -0,0 +1,324 @@
+use crate::types::{Connection, HttpStatusCode, Request, Response, Server, Tls, Transport};
+
+use openssl::error::ErrorStack;
+use openssl::pkey::PKey;
+use openssl::ssl::{
+    HandshakeError, NameType, SniError, SslAcceptor, SslAcceptorBuilder, SslContext, SslMethod,
+    SslStream,
+};
+use openssl::x509::X509;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+
+fn to_io_error(err: ErrorStack) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn handshake_failed<S>(err: HandshakeError<S>) -> io::Error
+where
+    S: Debug,
+{
+    io::Error::new(io::ErrorKind::ConnectionAborted, err.to_string())
+}
+
+// the first certificate in `cert_pem` is the server's, any after it are intermediates
+fn acceptor(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<SslAcceptorBuilder> {
+    let mut chain = X509::stack_from_pem(cert_pem)
+        .map_err(to_io_error)?
+        .into_iter();
+    let cert = chain.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "No certificate in the pem data")
+    })?;
+    let key = PKey::private_key_from_pem(key_pem).map_err(to_io_error)?;
+
+    let mut builder =
+        SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).map_err(to_io_error)?;
+    builder.set_certificate(&cert).map_err(to_io_error)?;
+    for intermediate in chain {
+        builder
+            .add_extra_chain_cert(intermediate)
+            .map_err(to_io_error)?;
+    }
+    builder.set_private_key(&key).map_err(to_io_error)?;
+    builder.check_private_key().map_err(to_io_error)?;
+
+    Ok(builder)
+}
+
+// `*.example.com` covers `www.example.com` but neither `example.com` nor `a.b.example.com`
+fn find_host<'a>(hosts: &'a HashMap<String, SslContext>, name: &str) -> Option<&'a SslContext> {
+    let name = name.to_ascii_lowercase();
+    hosts.get(&name).or_else(|| {
+        let (_, parent) = name.split_once('.')?;
+        hosts.get(&format!("*.{}", parent))
+    })
+}
+
+impl Tls {
+    // the certificate used when the client doesn't send a name or sends one we don't know
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Tls> {
+        Ok(Tls {
+            default: acceptor(cert_pem, key_pem)?,
+            hosts: HashMap::new(),
+        })
+    }
+
+    pub fn from_pem_files<C, K>(cert_path: C, key_path: K) -> io::Result<Tls>
+    where
+        C: AsRef<Path>,
+        K: AsRef<Path>,
+    {
+        Tls::from_pem(&std::fs::read(cert_path)?, &std::fs::read(key_path)?)
+    }
+
+    // another certificate, picked through sni when a client asks for `hostname`
+    pub fn host(mut self, hostname: &str, cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Tls> {
+        let context = acceptor(cert_pem, key_pem)?.build().into_context();
+        self.hosts.insert(hostname.to_ascii_lowercase(), context);
+
+        Ok(self)
+    }
+
+    pub(crate) fn build(self) -> SslAcceptor {
+        let Tls { mut default, hosts } = self;
+
+        if !hosts.is_empty() {
+            default.set_servername_callback(move |ssl, _| {
+                let name = ssl.servername(NameType::HOST_NAME).map(str::to_owned);
+                if let Some(context) = name.and_then(|name| find_host(&hosts, &name)) {
+                    ssl.set_ssl_context(context)
+                        .map_err(|_| SniError::ALERT_FATAL)?;
+                }
+
+                Ok(())
+            });
+        }
+
+        default.build()
+    }
+}
+
+impl Connection {
+    // unix sockets get the same handshake, a server set up for tls never answers in plaintext
+    pub(crate) fn into_tls_transport(
+        self,
+        acceptor: &SslAcceptor,
+    ) -> io::Result<Box<dyn Transport>> {
+        match self {
+            Connection::Tcp(stream) => {
+                Ok(Box::new(acceptor.accept(stream).map_err(handshake_failed)?))
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                Ok(Box::new(acceptor.accept(stream).map_err(handshake_failed)?))
+            }
+        }
+    }
+}
+
+impl Transport for SslStream<TcpStream> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().peer_addr().ok()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().local_addr().ok()
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(unix)]
+impl Transport for SslStream<UnixStream> {
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+impl<F> Server<F>
+where
+    F: Fn(&Request, &mut Response) -> Result<HttpStatusCode, Box<dyn std::error::Error>>
+        + Send
+        + 'static,
+{
+    // every connection is expected to start with a tls handshake from now on
+    pub fn set_tls(&mut self, tls: Tls) {
+        self.tls = Some(Arc::new(tls.build()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::{ListenAddr, Listener, Method};
+
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::{BigNum, MsbOption};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::ssl::{SslConnector, SslVerifyMode};
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::X509NameBuilder;
+
+    use std::io::{Read, Write};
+    use std::thread;
+
+    // a throwaway self-signed certificate and key for `name`, both as pem
+    fn self_signed(name: &str) -> (Vec<u8>, Vec<u8>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut serial = BigNum::new().unwrap();
+        serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&serial.to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns(name)
+            .build(&cert.x509v3_context(None, None))
+            .unwrap();
+        cert.append_extension(san).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (
+            cert.build().to_pem().unwrap(),
+            key.private_key_to_pem_pkcs8().unwrap(),
+        )
+    }
+
+    // fetches `/` over tls asking for `name`, returning the response and the name on the certificate
+    fn get<S>(socket: S, name: &str) -> (String, String)
+    where
+        S: Read + Write + Debug,
+    {
+        let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let connector = connector.build();
+
+        let mut stream = connector
+            .configure()
+            .unwrap()
+            .verify_hostname(false)
+            .connect(name, socket)
+            .unwrap();
+
+        let certificate = stream.ssl().peer_certificate().unwrap();
+        let common_name = certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .map(|entry| String::from_utf8_lossy(entry.data().as_slice()).into_owned())
+            .unwrap();
+
+        stream
+            .write_all(format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", name).as_bytes())
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        (response, common_name)
+    }
+
+    #[test]
+    fn it_rejects_mismatched_keys() {
+        let (cert, _) = self_signed("a.test");
+        let (_, key) = self_signed("b.test");
+
+        assert!(Tls::from_pem(&cert, &key).is_err());
+        assert!(Tls::from_pem(b"junk", b"junk").is_err());
+    }
+
+    #[test]
+    fn it_serves_https_with_sni() {
+        let (cert, key) = self_signed("default.test");
+        let (api_cert, api_key) = self_signed("api.example.test");
+        let (wildcard_cert, wildcard_key) = self_signed("*.example.test");
+        let tls = Tls::from_pem(&cert, &key)
+            .unwrap()
+            .host("api.example.test", &api_cert, &api_key)
+            .unwrap()
+            .host("*.example.test", &wildcard_cert, &wildcard_key)
+            .unwrap();
+
+        let mut server = Server::new("127.0.0.1:0");
+        let addr = match server.listener.as_ref().and_then(Listener::listen_addr) {
+            Some(ListenAddr::Tcp(addr)) => addr,
+            _ => unreachable!("bound to a tcp address"),
+        };
+        server.set_tls(tls);
+        server.on_all(|req, res| {
+            assert_eq!(req.method, Method::Get);
+            res.text(format!("secure={}", req.is_secure()));
+
+            Ok(HttpStatusCode::Code200)
+        });
+        let handle = thread::spawn(move || {
+            for _ in 0..3 {
+                server.listen_once();
+            }
+        });
+
+        let (response, name) = get(TcpStream::connect(addr).unwrap(), "api.example.test");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nsecure=true"));
+        assert_eq!(name, "api.example.test");
+
+        assert_eq!(
+            get(TcpStream::connect(addr).unwrap(), "www.example.test").1,
+            "*.example.test"
+        );
+        assert_eq!(
+            get(TcpStream::connect(addr).unwrap(), "other.test").1,
+            "default.test"
+        );
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_serves_https_on_unix_sockets() {
+        let (cert, key) = self_signed("local.test");
+        let path = std::env::temp_dir().join(format!("rebar-tls-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut server = Server::new_unix(&path, 0o600).unwrap();
+        server.set_tls(Tls::from_pem(&cert, &key).unwrap());
+        server.on_all(|req, res| {
+            res.text(format!("secure={}", req.is_secure()));
+
+            Ok(HttpStatusCode::Code200)
+        });
+        let handle = thread::spawn(move || server.listen_once());
+
+        let (response, name) = get(UnixStream::connect(&path).unwrap(), "local.test");
+        assert!(response.ends_with("\r\n\r\nsecure=true"));
+        assert_eq!(name, "local.test");
+
+        handle.join().unwrap();
+    }
+}
@@ -1,8 +1,15 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
 use std::marker::Send;
-use std::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 pub struct Server<F>
 where
@@ -13,8 +20,54 @@ where
         + Send
         + 'static,
 {
-    pub(crate) listener: TcpListener,
+    // `None` for a server that only answers through `serve_transport` and `test`
+    pub(crate) listener: Option<Listener>,
+    // a connection that was already waiting on an adopted socket, answered before the next accept
+    pub(crate) pending: Option<Connection>,
     pub(crate) handler: Arc<Mutex<Option<F>>>,
+    pub(crate) middleware: Vec<Arc<dyn Middleware>>,
+    pub(crate) config: ParseConfig,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<Arc<openssl::ssl::SslAcceptor>>,
+    pub(crate) shutdown: ShutdownHandle,
+    pub(crate) shutdown_timeout: Duration,
+    // connections still being answered, `listen` waits for these before it returns
+    pub(crate) in_flight: Arc<(Mutex<usize>, Condvar)>,
+}
+
+// stops a `Server::listen` running on another thread, see `Server::shutdown_handle`
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    pub(crate) stopping: Arc<AtomicBool>,
+    pub(crate) wake: Option<ListenAddr>,
+}
+
+// `before` runs in the order middleware was added and can answer the request itself,
+// `after` runs in reverse order once the response is ready to be sent
+pub trait Middleware: Send + Sync {
+    fn before(&self, _req: &mut Request, _res: &mut Response) -> Option<HttpStatusCode> {
+        None
+    }
+
+    fn after(&self, _req: &Request, _res: &mut Response) {}
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ParseConfig {
+    pub(crate) max_head_size: usize,
+    pub(crate) max_body_size: u64,
+    pub(crate) body_memory_limit: usize,
+    pub(crate) decompress_bodies: bool,
+    pub(crate) max_decompressed_size: u64,
+    pub(crate) trusted_proxies: Vec<TrustedProxy>,
+    pub(crate) trust_addressless_peers: bool,
+}
+
+// an address or cidr block whose `Forwarded` and `X-Forwarded-For` headers are believed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct TrustedProxy {
+    pub(crate) network: IpAddr,
+    pub(crate) prefix: u8,
 }
 
 #[derive(Debug, PartialEq)]
@@ -34,6 +87,7 @@ pub type Result<T> = std::result::Result<T, RebarError>;
 pub enum RebarError {
     ParseError(HttpParseError),
     TemplateError(TemplateError),
+    JsonError(JsonError),
 }
 
 #[derive(Debug, PartialEq)]
@@ -42,6 +96,16 @@ pub enum HttpParseError {
     InvalidPath,
     InvalidHttpVersion,
     InvalidHeaderSyntax,
+    InvalidContentLength,
+    InvalidChunkedEncoding,
+    HeadersTooLarge,
+    PayloadTooLarge,
+    UnsupportedEncoding(String),
+    InvalidEncodedBody,
+
+    UnsupportedMediaType(Option<String>),
+    InvalidJson(JsonError),
+    InvalidMultipart(String),
 
     Other(String),
 }
@@ -64,6 +128,27 @@ pub enum TemplateError {
     MissingVariable(String),
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum JsonError {
+    UnexpectedChar(char, usize),
+    UnexpectedEof,
+    InvalidNumber(usize),
+    InvalidEscape(usize),
+    InvalidUnicode(usize),
+    TrailingCharacters(usize),
+    TooDeep,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(BTreeMap<String, JsonValue>),
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum HttpVersion {
     Http1_1,
@@ -81,76 +166,343 @@ pub struct Request {
     pub headers: Headers,
 
     pub body: Option<String>,
+    // set instead of `body` when the payload is binary or was too large to keep in memory
+    pub(crate) raw_body: Option<Spooled>,
+
+    pub(crate) session: RefCell<Option<Session>>,
+    pub(crate) csp_nonce: Option<String>,
+    pub(crate) csrf_secret: Option<[u8; 32]>,
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) local_addr: Option<SocketAddr>,
+    pub(crate) client_ip: Option<IpAddr>,
+    pub(crate) secure: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TempFile {
+    pub(crate) path: PathBuf,
+    pub(crate) len: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Spooled {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MultipartLimits {
+    pub max_part_size: u64,
+    pub max_total_size: u64,
+    pub max_parts: usize,
+    pub max_header_size: usize,
+    pub memory_threshold: usize,
+}
+
+pub struct Multipart<'a> {
+    pub(crate) reader: Box<dyn Read + 'a>,
+    pub(crate) buf: Vec<u8>,
+    pub(crate) delimiter: Vec<u8>,
+    pub(crate) limits: MultipartLimits,
+    pub(crate) state: MultipartState,
+    pub(crate) read_total: u64,
+    pub(crate) parts: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum MultipartState {
+    Preamble,
+    Boundary,
+    Done,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MultipartPart {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: Headers,
+
+    pub(crate) data: Spooled,
+}
+
+// only the code takes part in equality, so the named constants work as match patterns
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct HttpStatusCode {
+    pub(crate) code: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Form(pub Vec<(String, String)>);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CookieJar(pub Vec<(String, String)>);
+
+#[derive(Clone)]
+pub struct Key {
+    pub(crate) signing: [u8; 32],
+    pub(crate) encryption: [u8; 32],
+}
+
+// the first key signs and encrypts new cookies, the rest only verify old ones while keys rotate
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    pub(crate) keys: Vec<Key>,
+}
+
+pub struct SignedJar<'a> {
+    pub(crate) jar: &'a CookieJar,
+    pub(crate) keys: &'a Keyring,
+}
+
+pub struct PrivateJar<'a> {
+    pub(crate) jar: &'a CookieJar,
+    pub(crate) keys: &'a Keyring,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SessionRecord {
+    pub values: HashMap<String, String>,
+    pub created: SystemTime,
+    pub accessed: SystemTime,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    // `None` until the session is first saved
+    pub(crate) id: Option<String>,
+    pub(crate) record: SessionRecord,
+
+    pub(crate) changed: bool,
+    pub(crate) regenerate: bool,
+    pub(crate) destroyed: bool,
+}
+
+pub trait SessionStore: Send + Sync {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>>;
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()>;
+    fn remove(&self, id: &str) -> io::Result<()>;
+    fn purge(&self, expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    pub(crate) sessions: Mutex<HashMap<String, SessionRecord>>,
+}
+
+#[derive(Debug)]
+pub struct FileStore {
+    pub(crate) dir: PathBuf,
+}
+
+pub struct Sessions {
+    pub(crate) store: Arc<dyn SessionStore>,
+    // every attribute but the value is used for the session cookie
+    pub(crate) cookie: Cookie,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) absolute_timeout: Option<Duration>,
+    pub(crate) requests: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConditionalGet;
+
+#[derive(Debug, Clone)]
+pub struct Compression {
+    pub(crate) threshold: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+pub(crate) type KeyExtractor = Box<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+// a token bucket per client that holds `limit` requests and refills over `window`
+pub struct RateLimit {
+    pub(crate) limit: u32,
+    pub(crate) window: Duration,
+    pub(crate) max_keys: usize,
+    pub(crate) key: KeyExtractor,
+    pub(crate) buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bucket {
+    pub(crate) tokens: f64,
+    pub(crate) updated: Instant,
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum HttpStatusCode {
-    Code100,
-    Code101,
-    Code103,
-    Code200,
-    Code201,
-    Code202,
-    Code203,
-    Code204,
-    Code205,
-    Code206,
-    Code300,
-    Code301,
-    Code302,
-    Code303,
-    Code304,
-    Code307,
-    Code308,
-    Code400,
-    Code401,
-    Code402,
-    Code403,
-    Code404,
-    Code405,
-    Code406,
-    Code407,
-    Code408,
-    Code409,
-    Code410,
-    Code411,
-    Code412,
-    Code413,
-    Code414,
-    Code415,
-    Code416,
-    Code417,
-    Code418,
-    Code422,
-    Code425,
-    Code426,
-    Code428,
-    Code429,
-    Code431,
-    Code451,
-    Code500,
-    Code501,
-    Code502,
-    Code503,
-    Code504,
-    Code505,
-    Code506,
-    Code507,
-    Code508,
-    Code510,
-    Code511,
+pub enum Credentials {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+pub struct Authentication {
+    // always starts and ends with `/`
+    pub(crate) prefix: String,
+    pub(crate) scheme: &'static str,
+    pub(crate) realm: String,
+    pub(crate) verify: Box<dyn Fn(&Credentials) -> bool + Send + Sync>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Csrf {
+    pub(crate) keys: Keyring,
+    pub(crate) cookie: Cookie,
+    pub(crate) field: String,
+    pub(crate) header: String,
+}
+
+pub struct Cors {
+    pub(crate) origins: CorsOrigins,
+    pub(crate) methods: Vec<String>,
+    pub(crate) headers: Vec<String>,
+    pub(crate) exposed_headers: Vec<String>,
+    pub(crate) credentials: bool,
+    pub(crate) max_age: Option<Duration>,
+}
+
+pub(crate) enum CorsOrigins {
+    Any,
+    List(Vec<String>),
+    Predicate(Box<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum ContentCoding {
+    Gzip,
+    Deflate,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ByteRanges {
+    // a malformed or unusable `Range` header is treated as if it wasn't sent
+    Ignore,
+    Unsatisfiable,
+    Satisfiable(Vec<(u64, u64)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    // always starts and ends with `/`
+    pub(crate) prefix: String,
+    pub(crate) root: PathBuf,
+    pub(crate) index: Option<String>,
+    pub(crate) listings: bool,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Headers(pub HashMap<String, String>);
 
+// where a `Server` accepts connections; a unix socket it bound itself keeps the path
+// so the file can be removed again once the server is dropped
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+// somewhere to connect to so a blocked `accept` returns
+#[derive(Clone)]
+pub(crate) enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+// a request to run through a server's middleware and handler without any socket,
+// see `Server::test`
+pub struct TestRequest {
+    pub(crate) method: Method,
+    pub(crate) target: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) cookies: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) secure: bool,
+}
+
+// what the handler and middleware made of a `TestRequest`, as it would have been sent
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: HttpStatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    pub cookies: Vec<Cookie>,
+}
+
+// a connection fresh out of a `Listener`, before any tls handshake
+pub(crate) enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+// what a request is read from and its response written back to; the addresses are
+// `None` for anything that isn't a network socket
+pub trait Transport: Read + Write + Send {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+// a transport made of two halves, e.g. a `Cursor` holding a request and a `Vec` the
+// response is written into, see `Server::serve_transport`
+pub struct Duplex<R, W> {
+    pub input: R,
+    pub output: W,
+}
+
+// certificates for `Server::set_tls`, picked by the name the client asks for
+#[cfg(feature = "tls")]
+pub struct Tls {
+    pub(crate) default: openssl::ssl::SslAcceptorBuilder,
+    pub(crate) hosts: HashMap<String, openssl::ssl::SslContext>,
+}
+
 pub struct Response {
-    pub(crate) stream: TcpStream,
+    pub(crate) stream: Box<dyn Transport>,
 
     pub headers: Headers,
     pub http_version: HttpVersion,
     pub status: HttpStatusCode,
     pub body: Vec<u8>,
+
+    pub(crate) cookies: Vec<Cookie>,
+    // a custom reason phrase, only sent while the status is still the one it was set for
+    pub(crate) reason: Option<(HttpStatusCode, Cow<'static, str>)>,
 }
 
 pub(crate) trait LogError {